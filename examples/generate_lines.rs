@@ -0,0 +1,29 @@
+//! Writes a large synthetic log to stdout, for measuring search throughput.
+//!
+//! ```text
+//! cargo run --release --example generate_lines -- 10000000 > big.log
+//! time target/release/grep-lite-2 -c needle big.log
+//! ```
+
+use std::io::{self, BufWriter, Write};
+
+fn main() -> io::Result<()> {
+    let lines: usize = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1_000_000);
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for i in 0..lines {
+        if i % 1000 == 0 {
+            writeln!(out, "{} found the needle in the haystack", i)?;
+        } else {
+            writeln!(
+                out,
+                "{} lorem ipsum dolor sit amet, consectetur adipiscing elit",
+                i
+            )?;
+        }
+    }
+    out.flush()
+}
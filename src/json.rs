@@ -0,0 +1,502 @@
+//! The messages printed by `grep-lite --json`, one JSON object per line.
+//!
+//! Each message is tagged with its `type` and carries its fields under
+//! `data`, in the same shape as ripgrep's JSON output. A file's messages
+//! start with a `begin` and finish with an `end`, and a `summary` follows
+//! the last file:
+//!
+//! ```text
+//! {"type":"begin","data":{"path":{"text":"src/main.rs"}}}
+//! {"type":"match","data":{"path":{"text":"src/main.rs"},"lines":{"text":"fn main() {"},"line_number":3,"absolute_offset":24,"submatches":[{"match":{"text":"main"},"start":3,"end":7}]}}
+//! {"type":"end","data":{"path":{"text":"src/main.rs"},"stats":{"count":1}}}
+//! {"type":"summary","data":{"stats":{"count":1}}}
+//! ```
+//!
+//! Text is given as `{"text":"..."}`, or as `{"bytes":"..."}` holding
+//! base64 when it isn't valid UTF-8, as for a line such as `foo \xff bad`:
+//!
+//! ```text
+//! {"type":"match","data":{"path":{"text":"log.txt"},"lines":{"bytes":"Zm9vIP8gYmFk"},"line_number":2,"absolute_offset":7,"submatches":[{"match":{"text":"foo"},"start":0,"end":3}]}}
+//! ```
+//!
+//! The types can be read back with `serde_json`:
+//!
+//! ```
+//! use grep_lite::json::{Message, Stats, Text};
+//!
+//! let line = r#"{"type":"summary","data":{"stats":{"count":3}}}"#;
+//! let message: Message = serde_json::from_str(line).unwrap();
+//!
+//! assert_eq!(message, Message::summary(3));
+//! assert_eq!(serde_json::to_string(&message).unwrap(), line);
+//!
+//! let end = Message::end("notes.txt", 0);
+//! let text = serde_json::to_string(&end).unwrap();
+//! let Message::End(end) = serde_json::from_str(&text).unwrap() else {
+//!     panic!("expected an end message");
+//! };
+//! assert_eq!(end.path, Text::from("notes.txt"));
+//! assert_eq!(
+//!     serde_json::to_string(&Text::from(&b"\xff"[..])).unwrap(),
+//!     r#"{"bytes":"/w=="}"#
+//! );
+//! assert_eq!(end.stats, Stats { count: 0 });
+//! ```
+
+use crate::CompiledRegex;
+use serde::{Deserialize, Serialize};
+
+/// One line of `--json` output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Message {
+    /// A file is about to have lines printed or be counted
+    Begin(Begin),
+    /// A matching line
+    Match(Lines),
+    /// A line of context around a match
+    Context(Lines),
+    /// Everything from a file has been printed
+    End(End),
+    /// Every file has been searched
+    Summary(Summary),
+}
+
+impl Message {
+    /// The `begin` message for the file at `path`.
+    pub fn begin(path: &str) -> Self {
+        Message::Begin(Begin { path: path.into() })
+    }
+
+    /// A `match` message for `line`, giving where `regex` matches in it.
+    /// Lines found by `--invert-match` should be given no regex, as nothing
+    /// in them matches.
+    ///
+    /// ```
+    /// use grep_lite::{json::Message, SearchConfig};
+    ///
+    /// let search = SearchConfig::new("o+").build().unwrap();
+    /// let message = Message::matched("a.txt", 2, 4, "foo", Some(search.regex()));
+    ///
+    /// assert_eq!(
+    ///     serde_json::to_string(&message).unwrap(),
+    ///     r#"{"type":"match","data":{"path":{"text":"a.txt"},"lines":{"text":"foo"},"line_number":2,"absolute_offset":4,"submatches":[{"match":{"text":"oo"},"start":1,"end":3}]}}"#
+    /// );
+    /// ```
+    pub fn matched(
+        path: &str,
+        line_number: usize,
+        absolute_offset: u64,
+        line: &str,
+        regex: Option<&CompiledRegex>,
+    ) -> Self {
+        let submatches = regex
+            .into_iter()
+            .flat_map(|regex| regex.find_iter(line))
+            .map(|found| SubMatch {
+                matched: line[found.clone()].into(),
+                start: found.start,
+                end: found.end,
+            })
+            .collect();
+        Message::Match(Lines {
+            path: path.into(),
+            lines: line.into(),
+            line_number,
+            absolute_offset,
+            submatches,
+        })
+    }
+
+    /// A `match` message for a `line` that isn't valid UTF-8, given as its
+    /// bytes. Matches are found in its text with each invalid sequence
+    /// replaced by U+FFFD, as the search did, and given as offsets into
+    /// `line`.
+    ///
+    /// ```
+    /// use grep_lite::{json::{Message, Text}, SearchConfig};
+    ///
+    /// let search = SearchConfig::new("bad").build().unwrap();
+    /// let line = b"foo \xff bad";
+    /// let Message::Match(lines) = Message::matched_bytes("a.txt", 2, 7, line, Some(search.regex()))
+    /// else {
+    ///     panic!("expected a match message");
+    /// };
+    /// assert_eq!(lines.lines, Text::Bytes(line.to_vec()));
+    /// assert_eq!((lines.submatches[0].start, lines.submatches[0].end), (6, 9));
+    /// ```
+    pub fn matched_bytes(
+        path: &str,
+        line_number: usize,
+        absolute_offset: u64,
+        line: &[u8],
+        regex: Option<&CompiledRegex>,
+    ) -> Self {
+        let text = String::from_utf8_lossy(line);
+        let starts = byte_starts(line);
+        let submatches = regex
+            .into_iter()
+            .flat_map(|regex| regex.find_iter(&text))
+            .map(|found| {
+                let (start, end) = (starts[found.start], starts[found.end]);
+                SubMatch {
+                    matched: line[start..end].into(),
+                    start,
+                    end,
+                }
+            })
+            .collect();
+        Message::Match(Lines {
+            path: path.into(),
+            lines: line.into(),
+            line_number,
+            absolute_offset,
+            submatches,
+        })
+    }
+
+    /// A `context` message for `line`.
+    ///
+    /// ```
+    /// use grep_lite::json::Message;
+    ///
+    /// let Message::Context(lines) = Message::context("a.txt", 1, 0, "bar") else {
+    ///     panic!("expected a context message");
+    /// };
+    /// assert_eq!(lines.lines, "bar".into());
+    /// assert!(lines.submatches.is_empty());
+    /// ```
+    pub fn context(path: &str, line_number: usize, absolute_offset: u64, line: &str) -> Self {
+        Message::Context(Lines {
+            path: path.into(),
+            lines: line.into(),
+            line_number,
+            absolute_offset,
+            submatches: Vec::new(),
+        })
+    }
+
+    /// A `context` message for a `line` that isn't valid UTF-8, given as
+    /// its bytes.
+    pub fn context_bytes(
+        path: &str,
+        line_number: usize,
+        absolute_offset: u64,
+        line: &[u8],
+    ) -> Self {
+        Message::Context(Lines {
+            path: path.into(),
+            lines: line.into(),
+            line_number,
+            absolute_offset,
+            submatches: Vec::new(),
+        })
+    }
+
+    /// The `end` message for the file at `path`, after `count` lines were
+    /// counted in it.
+    pub fn end(path: &str, count: usize) -> Self {
+        Message::End(End {
+            path: path.into(),
+            stats: Stats { count },
+        })
+    }
+
+    /// The `summary` message, after `count` lines were counted in total.
+    pub fn summary(count: usize) -> Self {
+        Message::Summary(Summary {
+            stats: Stats { count },
+            totals: None,
+        })
+    }
+
+    /// The `summary` message for `--stats`, with everything else that was
+    /// counted.
+    ///
+    /// ```
+    /// use grep_lite::json::{Message, Totals};
+    ///
+    /// let totals = Totals {
+    ///     matched_lines: 2,
+    ///     matches: 3,
+    ///     files_with_matches: 1,
+    ///     files_searched: 4,
+    ///     lines_searched: 40,
+    ///     bytes_searched: 400,
+    ///     elapsed_micros: 12,
+    /// };
+    /// let Message::Summary(summary) = Message::summary_with_totals(2, totals.clone()) else {
+    ///     panic!("expected a summary message");
+    /// };
+    /// assert_eq!(summary.stats.count, 2);
+    /// assert_eq!(summary.totals, Some(totals));
+    /// ```
+    pub fn summary_with_totals(count: usize, totals: Totals) -> Self {
+        Message::Summary(Summary {
+            stats: Stats { count },
+            totals: Some(totals),
+        })
+    }
+}
+
+// Where each byte of `line`'s lossy text comes from in `line`, with one more
+// entry for the end. Each U+FFFD stands for a whole invalid sequence, so its
+// three bytes all map to where that starts.
+fn byte_starts(line: &[u8]) -> Vec<usize> {
+    let mut starts = Vec::with_capacity(line.len() + 1);
+    let mut start = 0;
+    for chunk in line.utf8_chunks() {
+        starts.extend(start..start + chunk.valid().len());
+        start += chunk.valid().len();
+        if !chunk.invalid().is_empty() {
+            starts.extend([start; 3]);
+            start += chunk.invalid().len();
+        }
+    }
+    starts.push(start);
+    starts
+}
+
+/// Text from a file or its name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Text {
+    /// Valid UTF-8, given as it is
+    Text(String),
+    /// Anything else, given as base64
+    Bytes(#[serde(with = "base64")] Vec<u8>),
+}
+
+impl From<&str> for Text {
+    fn from(text: &str) -> Self {
+        Text::Text(text.to_string())
+    }
+}
+
+impl From<&[u8]> for Text {
+    /// Valid UTF-8 is given as text, and anything else as bytes.
+    fn from(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => text.into(),
+            Err(_) => Text::Bytes(bytes.to_vec()),
+        }
+    }
+}
+
+// Standard base64 with padding, as ripgrep gives bytes in
+mod base64 {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+        for chunk in bytes.chunks(3) {
+            let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+                group | u32::from(byte) << (16 - 8 * i)
+            });
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    encoded.push(ALPHABET[(group >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    encoded.push('=');
+                }
+            }
+        }
+        serializer.serialize_str(&encoded)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        let digits = encoded.trim_end_matches('=').as_bytes();
+        if encoded.len() % 4 != 0 || digits.len() % 4 == 1 {
+            return Err(de::Error::custom("invalid base64 length"));
+        }
+        let mut bytes = Vec::with_capacity(digits.len() * 3 / 4);
+        for chunk in digits.chunks(4) {
+            let mut group = 0u32;
+            for (i, &digit) in chunk.iter().enumerate() {
+                let value = ALPHABET
+                    .iter()
+                    .position(|&c| c == digit)
+                    .ok_or_else(|| de::Error::custom("invalid base64 digit"))?;
+                group |= (value as u32) << (18 - 6 * i);
+            }
+            bytes.extend(&group.to_be_bytes()[1..chunk.len()]);
+        }
+        Ok(bytes)
+    }
+}
+
+/// The data of a `begin` message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Begin {
+    pub path: Text,
+}
+
+/// The data of a `match` or `context` message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lines {
+    pub path: Text,
+    /// The line, without its line ending, given as bytes if it isn't valid
+    /// UTF-8
+    pub lines: Text,
+    /// The 1-based number of the line
+    pub line_number: usize,
+    /// How many bytes into the file the line starts
+    pub absolute_offset: u64,
+    /// Every match within the line, which is empty for context and for
+    /// lines found by `--invert-match`
+    pub submatches: Vec<SubMatch>,
+}
+
+/// A match within a line, as byte offsets into it. Its text is given as
+/// bytes if it isn't valid UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubMatch {
+    #[serde(rename = "match")]
+    pub matched: Text,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The data of an `end` message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct End {
+    pub path: Text,
+    pub stats: Stats,
+}
+
+/// The data of a `summary` message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Summary {
+    pub stats: Stats,
+    /// Only given with `--stats`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub totals: Option<Totals>,
+}
+
+/// What was counted in a file, or in every file for a summary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    /// The number of matching lines, or of matches with `--count-matches`.
+    /// A search for `--files-with-matches` stops at the first match, so
+    /// counts at most 1 for each file.
+    pub count: usize,
+}
+
+/// Everything counted over a whole run, for `--stats`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Totals {
+    pub matched_lines: usize,
+    pub matches: usize,
+    pub files_with_matches: usize,
+    pub files_searched: usize,
+    pub lines_searched: usize,
+    pub bytes_searched: u64,
+    pub elapsed_micros: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SearchConfig;
+
+    // Serializes `message` and reads it back, which should give it unchanged
+    fn round_trip(message: &Message) -> Message {
+        let text = serde_json::to_string(message).unwrap();
+        serde_json::from_str(&text).unwrap()
+    }
+
+    #[test]
+    fn match_messages_round_trip_with_their_submatches() {
+        let search = SearchConfig::new("o+").build().unwrap();
+        let message = Message::matched("src/a.rs", 3, 24, "foo boo", Some(search.regex()));
+
+        assert_eq!(round_trip(&message), message);
+        let Message::Match(lines) = message else {
+            panic!("expected a match message");
+        };
+        assert_eq!(
+            lines.submatches,
+            [
+                SubMatch {
+                    matched: "oo".into(),
+                    start: 1,
+                    end: 3,
+                },
+                SubMatch {
+                    matched: "oo".into(),
+                    start: 5,
+                    end: 7,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn inverted_match_messages_round_trip_without_submatches() {
+        let message = Message::matched("a.txt", 1, 0, "nothing here", None);
+
+        assert_eq!(round_trip(&message), message);
+    }
+
+    #[test]
+    fn context_messages_round_trip() {
+        let message = Message::context("a.txt", 7, 120, "around \"it\" \u{e9}");
+
+        assert_eq!(round_trip(&message), message);
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"type":"context","data":{"path":{"text":"a.txt"},"lines":{"text":"around \"it\" é"},"line_number":7,"absolute_offset":120,"submatches":[]}}"#
+        );
+    }
+
+    #[test]
+    fn bytes_are_base64_with_padding() {
+        for (bytes, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foo \xff bad", "Zm9vIP8gYmFk"),
+            (b"\xfb\xff\xfe", "+//+"),
+        ] {
+            let text = Text::Bytes(bytes.to_vec());
+            let json = format!(r#"{{"bytes":"{}"}}"#, encoded);
+            assert_eq!(serde_json::to_string(&text).unwrap(), json);
+            assert_eq!(serde_json::from_str::<Text>(&json).unwrap(), text);
+        }
+        assert!(serde_json::from_str::<Text>(r#"{"bytes":"Zm9"}"#).is_err());
+        assert!(serde_json::from_str::<Text>(r#"{"bytes":"Zm9*"}"#).is_err());
+    }
+
+    #[test]
+    fn match_messages_for_bytes_give_offsets_into_them() {
+        let search = SearchConfig::new("b+").build().unwrap();
+        // A lone continuation byte, then a truncated three-byte sequence
+        let line = b"\x80ab\xe2\x82bb";
+        let message = Message::matched_bytes("a.txt", 1, 0, line, Some(search.regex()));
+
+        assert_eq!(round_trip(&message), message);
+        let Message::Match(lines) = message else {
+            panic!("expected a match message");
+        };
+        assert_eq!(lines.lines, Text::Bytes(line.to_vec()));
+        assert_eq!(
+            lines.submatches,
+            [
+                SubMatch {
+                    matched: "b".into(),
+                    start: 2,
+                    end: 3,
+                },
+                SubMatch {
+                    matched: "bb".into(),
+                    start: 5,
+                    end: 7,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,3129 @@
+//! The search behind the `grep-lite` command, for use from other programs.
+//!
+//! A search is set up with [`SearchConfig::new`] and compiled into a
+//! [`CompiledSearch`], which [`search_reader`] and [`search_file`] run to
+//! collect the matches it finds:
+//!
+//! ```
+//! use grep_lite::{search_reader, SearchConfig};
+//!
+//! let search = SearchConfig::new("fn \\w+").build().unwrap();
+//! let text = "struct Grep;\nfn main() {}\n";
+//! let matches = search_reader(text.as_bytes(), &search).unwrap();
+//!
+//! assert_eq!(matches.len(), 1);
+//! assert_eq!(matches[0].line_number, 2);
+//! assert_eq!(matches[0].line, "fn main() {}");
+//! ```
+//!
+//! To handle matches as they are found instead, iterate over
+//! [`CompiledSearch::search`], or pass a [`Sink`] to
+//! [`search_reader_with_sink`] or [`search_file_with_sink`] for every line
+//! that would be printed.
+//!
+//! The [`json`] module has the messages printed by `grep-lite --json`.
+
+use flate2::read::GzDecoder;
+use memchr::{memchr, memmem::Finder, memrchr};
+use memmap2::Mmap;
+use regex::{Regex, RegexBuilder};
+use regex_syntax::{
+    ast::{self, Ast, ClassSet, ClassSetItem, LiteralKind},
+    hir::{Hir, HirKind},
+    ParserBuilder,
+};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Result, Write},
+    ops::{AddAssign, Range},
+    path::Path,
+    sync::OnceLock,
+};
+use zip::ZipArchive;
+
+#[cfg(feature = "async-tokio")]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+pub mod json;
+
+/// What to search for and which lines to report. The fields mirror the
+/// `grep-lite` flags of the same names.
+///
+/// Build one with [`SearchConfig::new`], or set the fields directly and
+/// compile it with [`CompiledSearch::new`].
+#[derive(Debug, Clone)]
+pub struct SearchConfig {
+    /// The regular expression to search for
+    pub pattern: String,
+    /// More patterns to search for, as given by repeating `-e`. A line
+    /// matches if any pattern does.
+    pub extra_patterns: Vec<String>,
+    /// Search for the pattern as plain text rather than a regex
+    pub fixed_strings: bool,
+    /// Match case-insensitively. Takes precedence over `case_sensitive`
+    /// and `smart_case`.
+    pub ignore_case: bool,
+    /// Match case-sensitively, even with `smart_case`
+    pub case_sensitive: bool,
+    /// Match case-insensitively unless the pattern contains an uppercase
+    /// letter
+    pub smart_case: bool,
+    /// Let matches span more than one line
+    pub multiline: bool,
+    /// With `multiline`, let `.` match newlines too
+    pub multiline_dotall: bool,
+    /// Let `\w`, `\d`, `\s`, `\b` and case-insensitive matching take in
+    /// all of Unicode, as they do by default. Without it they only know
+    /// ASCII, and `.`, `\W` or negated classes such as `[^a]` fail to
+    /// compile, as they could match half a character; write those as
+    /// `(?u:.)`.
+    pub unicode: bool,
+    /// Compile the pattern with PCRE2 rather than the regex crate, for
+    /// look-around and backreferences. Needs the `pcre2` feature.
+    pub pcre2: bool,
+    /// The most memory, in bytes, the compiled regex may take, instead of
+    /// the regex crate's default. Patterns needing more fail to compile.
+    pub regex_size_limit: Option<usize>,
+    /// The most memory, in bytes, the regex crate's lazy DFA may use as it
+    /// searches, instead of its default
+    pub dfa_size_limit: Option<usize>,
+    /// Report the lines that don't match instead
+    pub invert_match: bool,
+    /// Stop after this many matching lines, once any context after the last
+    /// of them has been reported
+    pub max_count: Option<usize>,
+    /// Report the number of matching lines instead of the lines
+    pub count: bool,
+    /// Report the number of matches instead of the lines, counting each
+    /// occurrence on a line
+    pub count_matches: bool,
+    /// Only count the matching lines without reporting anything, for a total
+    /// kept by the caller
+    pub total: bool,
+    /// Report nothing, and stop at the first match
+    pub quiet: bool,
+    /// Only report whether there was a match, stopping at the first one.
+    /// With `count` or `count_matches`, the count is reported instead, and
+    /// only when it isn't 0.
+    pub files_with_matches: bool,
+    /// Lines of context to report after each match
+    pub after_context: usize,
+    /// Lines of context to report before each match
+    pub before_context: usize,
+    /// Also recognise bzip2 and xz files by their first bytes, not only by
+    /// their extension. Files in no recognised format are read as they are.
+    pub decompress: bool,
+    /// Search inside every file as a zip archive
+    pub zip: bool,
+    /// Always memory-map files
+    pub mmap: bool,
+    /// Never memory-map files
+    pub no_mmap: bool,
+    /// Memory-map files of at least this many bytes
+    pub mmap_threshold: u64,
+    /// Report a [`MatchEvent::Stats`] at the end of each input
+    pub stats: bool,
+    /// Report every line that doesn't match as context, so the whole input
+    /// is reported. `after_context` and `before_context` are ignored.
+    pub passthru: bool,
+    /// With `invert_match`, also report every line that matches as context,
+    /// so the parts of lines between matches can be shown, as
+    /// `grep-lite -o -v` does. Only the lines that don't match are counted,
+    /// and `after_context` and `before_context` are ignored.
+    pub only_unmatched: bool,
+    /// Split the input at NUL bytes rather than newlines, so that each
+    /// NUL-terminated record is searched and numbered as a line. Ignored with
+    /// `multiline`.
+    pub null_data: bool,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            pattern: String::new(),
+            extra_patterns: Vec::new(),
+            fixed_strings: false,
+            ignore_case: false,
+            case_sensitive: false,
+            smart_case: false,
+            multiline: false,
+            multiline_dotall: false,
+            unicode: true,
+            pcre2: false,
+            regex_size_limit: None,
+            dfa_size_limit: None,
+            invert_match: false,
+            max_count: None,
+            count: false,
+            count_matches: false,
+            total: false,
+            quiet: false,
+            files_with_matches: false,
+            after_context: 0,
+            before_context: 0,
+            decompress: false,
+            zip: false,
+            mmap: false,
+            no_mmap: false,
+            mmap_threshold: MMAP_THRESHOLD,
+            stats: false,
+            passthru: false,
+            only_unmatched: false,
+            null_data: false,
+        }
+    }
+}
+
+impl SearchConfig {
+    /// Starts building a search for `pattern`, with every other setting off.
+    ///
+    /// ```
+    /// use grep_lite::{search_reader, SearchConfig};
+    ///
+    /// let search = SearchConfig::new("a.c")
+    ///     .fixed_strings(true)
+    ///     .case_insensitive(true)
+    ///     .max_count(1)
+    ///     .build()
+    ///     .unwrap();
+    /// let matches = search_reader(&b"abc\nA.C\na.c\n"[..], &search).unwrap();
+    ///
+    /// assert_eq!(matches.len(), 1);
+    /// assert_eq!(matches[0].line, "A.C");
+    /// ```
+    // `new` returns the builder, as `SearchConfig::new(..).build()` reads well
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(pattern: &str) -> SearchConfigBuilder {
+        SearchConfigBuilder {
+            config: SearchConfig {
+                pattern: pattern.to_string(),
+                ..SearchConfig::default()
+            },
+        }
+    }
+
+    /// Whether any of the patterns uses look-around or backreferences, which
+    /// only PCRE2 supports. Patterns that are wrong in some other way first
+    /// don't count, and neither do `fixed_strings`.
+    ///
+    /// ```
+    /// use grep_lite::SearchConfig;
+    ///
+    /// let config = |pattern: &str| SearchConfig {
+    ///     pattern: pattern.to_string(),
+    ///     ..SearchConfig::default()
+    /// };
+    ///
+    /// assert!(config("foo(?!bar)").needs_pcre2());
+    /// assert!(config(r"(\w+) \1").needs_pcre2());
+    /// assert!(!config("(unclosed").needs_pcre2());
+    /// ```
+    pub fn needs_pcre2(&self) -> bool {
+        !self.fixed_strings
+            && std::iter::once(&self.pattern)
+                .chain(&self.extra_patterns)
+                .any(|pattern| {
+                    ast::parse::Parser::new()
+                        .parse(pattern)
+                        .err()
+                        .is_some_and(|e| {
+                            matches!(
+                                e.kind(),
+                                ast::ErrorKind::UnsupportedLookAround
+                                    | ast::ErrorKind::UnsupportedBackreference
+                            )
+                        })
+                })
+    }
+
+    // The byte that ends each line of the input
+    fn line_terminator(&self) -> u8 {
+        if self.null_data && !self.multiline {
+            b'\0'
+        } else {
+            b'\n'
+        }
+    }
+
+    // Whether every line is reported, those that aren't counted as context
+    fn reports_every_line(&self) -> bool {
+        self.passthru || (self.only_unmatched && self.invert_match)
+    }
+}
+
+/// Sets up a [`SearchConfig`] one setting at a time, from [`SearchConfig::new`].
+#[derive(Debug, Clone)]
+pub struct SearchConfigBuilder {
+    config: SearchConfig,
+}
+
+impl SearchConfigBuilder {
+    /// Match case-insensitively
+    pub fn case_insensitive(mut self, yes: bool) -> Self {
+        self.config.ignore_case = yes;
+        self
+    }
+
+    /// Report the lines that don't match instead
+    ///
+    /// ```
+    /// use grep_lite::{search_reader, SearchConfig};
+    ///
+    /// let search = SearchConfig::new("b").invert_match(true).build().unwrap();
+    /// let found = search_reader(&b"a\nb\nc\n"[..], &search).unwrap();
+    ///
+    /// assert_eq!(found.len(), 2);
+    /// assert_eq!(found[1].line, "c");
+    /// ```
+    pub fn invert_match(mut self, yes: bool) -> Self {
+        self.config.invert_match = yes;
+        self
+    }
+
+    /// Match with Unicode rules, as is the default, or with ASCII ones. Case
+    /// is then only ignored for ASCII letters.
+    ///
+    /// ```
+    /// use grep_lite::{search_reader, SearchConfig};
+    ///
+    /// // The Kelvin sign is an uppercase `k` in Unicode
+    /// let input = "\u{212A}\n".as_bytes();
+    /// let unicode = SearchConfig::new("k").case_insensitive(true).build().unwrap();
+    /// let ascii = SearchConfig::new("k")
+    ///     .case_insensitive(true)
+    ///     .unicode(false)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(search_reader(input, &unicode).unwrap().len(), 1);
+    /// assert!(search_reader(input, &ascii).unwrap().is_empty());
+    /// assert!(SearchConfig::new("a.c").unicode(false).build().is_err());
+    /// ```
+    pub fn unicode(mut self, yes: bool) -> Self {
+        self.config.unicode = yes;
+        self
+    }
+
+    /// Stop after this many matching lines
+    pub fn max_count(mut self, max_count: usize) -> Self {
+        self.config.max_count = Some(max_count);
+        self
+    }
+
+    /// Lines of context to report after each match
+    pub fn after_context(mut self, lines: usize) -> Self {
+        self.config.after_context = lines;
+        self
+    }
+
+    /// Lines of context to report before each match
+    pub fn before_context(mut self, lines: usize) -> Self {
+        self.config.before_context = lines;
+        self
+    }
+
+    /// Search for the pattern as plain text rather than a regex
+    pub fn fixed_strings(mut self, yes: bool) -> Self {
+        self.config.fixed_strings = yes;
+        self
+    }
+
+    /// Compiles the search, failing if the pattern is not a valid regex.
+    pub fn build(self) -> std::result::Result<CompiledSearch, GrepError> {
+        CompiledSearch::new(self.config)
+    }
+}
+
+// Decides which files inside an archive are searched, from the name each
+// would be printed with
+type MemberFilter = Box<dyn Fn(&Path) -> bool + Send + Sync>;
+
+/// A [`SearchConfig`] with its pattern compiled, ready to search any number
+/// of inputs.
+pub struct CompiledSearch {
+    config: SearchConfig,
+    pattern: Pattern,
+    member_filter: Option<MemberFilter>,
+}
+
+impl fmt::Debug for CompiledSearch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CompiledSearch")
+            .field("config", &self.config)
+            .field("pattern", &self.pattern)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CompiledSearch {
+    /// Compiles the pattern of `config`, failing if it is not a valid regex.
+    pub fn new(config: SearchConfig) -> std::result::Result<Self, GrepError> {
+        let pattern = Pattern::new(&config)?;
+        Ok(CompiledSearch {
+            config,
+            pattern,
+            member_filter: None,
+        })
+    }
+
+    /// Only searches the files in an archive whose names pass `filter`, as
+    /// they would be printed: `archive.zip!/path/in/zip`. Every file in an
+    /// archive is searched otherwise.
+    ///
+    /// ```
+    /// use grep_lite::SearchConfig;
+    ///
+    /// let search = SearchConfig::new("fn main")
+    ///     .build()
+    ///     .unwrap()
+    ///     .with_member_filter(|path| path.extension().is_some_and(|ext| ext == "rs"));
+    /// ```
+    pub fn with_member_filter(
+        mut self,
+        filter: impl Fn(&Path) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.member_filter = Some(Box::new(filter));
+        self
+    }
+
+    /// The settings the search was compiled from.
+    ///
+    /// ```
+    /// use grep_lite::SearchConfig;
+    ///
+    /// let search = SearchConfig::new("b").max_count(2).build().unwrap();
+    ///
+    /// assert_eq!(search.config().pattern, "b");
+    /// assert_eq!(search.config().max_count, Some(2));
+    /// ```
+    pub fn config(&self) -> &SearchConfig {
+        &self.config
+    }
+
+    /// The compiled regex, for highlighting matches within a line.
+    pub fn regex(&self) -> &CompiledRegex {
+        &self.pattern.regex
+    }
+
+    /// Each pattern compiled on its own, in the order given, for telling
+    /// which of them a match of [`CompiledSearch::regex`] came from. There
+    /// is just the one when there are no `extra_patterns`.
+    ///
+    /// ```
+    /// use grep_lite::{CompiledSearch, SearchConfig};
+    ///
+    /// let search = CompiledSearch::new(SearchConfig {
+    ///     pattern: "cat".to_string(),
+    ///     extra_patterns: vec!["dog".to_string()],
+    ///     ..SearchConfig::default()
+    /// })
+    /// .unwrap();
+    /// let found = search.regex().find("hot dog").unwrap();
+    ///
+    /// assert_eq!(search.pattern_index("hot dog", found.start), Some(1));
+    /// assert!(search.regexes()[1].is_match("hot dog"));
+    /// ```
+    pub fn regexes(&self) -> &[CompiledRegex] {
+        &self.pattern.regexes
+    }
+
+    /// Which of [`CompiledSearch::regexes`] the match of
+    /// [`CompiledSearch::regex`] starting at `start` in `line` came from: the
+    /// first one to match there, as the first alternative is preferred.
+    pub fn pattern_index(&self, line: &str, start: usize) -> Option<usize> {
+        self.pattern.regexes.iter().position(|regex| {
+            regex
+                .find_at(line, start)
+                .is_some_and(|found| found.start == start)
+        })
+    }
+
+    /// Searches `reader` lazily, returning an iterator over its matches. The
+    /// matches are given `filename`, as [`search_file`] would.
+    ///
+    /// ```
+    /// use grep_lite::SearchConfig;
+    ///
+    /// let search = SearchConfig::new("b").before_context(1).build().unwrap();
+    /// let mut matches = search.search(&b"a\nb\nc\nb\n"[..], Some("abc.txt"));
+    ///
+    /// let first = matches.next().unwrap().unwrap();
+    /// assert_eq!(first.to_string(), "abc.txt:1: a\nabc.txt:2: b");
+    /// assert_eq!(matches.next().unwrap().unwrap().line_number, 4);
+    /// assert!(matches.next().is_none());
+    /// ```
+    pub fn search<R: BufRead>(&self, reader: R, filename: Option<&str>) -> Matches<'_, R> {
+        Matches {
+            reader,
+            lines: LineSearch::new(self, filename),
+            line: String::new(),
+            error: None,
+            done: false,
+        }
+    }
+}
+
+/// A matching line found by a search, with any context printed around it.
+///
+/// Formatting a `Match` gives the lines as `grep-lite` prints them, without
+/// highlighting:
+///
+/// ```
+/// use grep_lite::{search_reader, SearchConfig};
+///
+/// let search = SearchConfig::new("b+").after_context(1).build().unwrap();
+/// let found = &search_reader(&b"a\nabba\nc\n"[..], &search).unwrap()[0];
+///
+/// assert_eq!(found.byte_offset, 2);
+/// assert_eq!(found.match_ranges, [1..3]);
+/// assert_eq!(found.to_string(), "2: abba\n3: c");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// The file the line is in, or `None` when searching a reader. Lines in
+    /// an archive are named `archive.zip!/path/in/zip`.
+    pub filename: Option<String>,
+    /// The 1-based number of the line
+    pub line_number: usize,
+    /// How many bytes into the input the line starts
+    pub byte_offset: u64,
+    /// The line, without its line ending. Invalid UTF-8 in it is replaced
+    /// by U+FFFD.
+    pub line: String,
+    /// The byte ranges of every match within `line`, which is empty for
+    /// lines found by `invert_match`
+    pub match_ranges: Vec<Range<usize>>,
+    /// The 1-based numbers and text of the context lines before this one
+    pub before_context: Vec<(usize, String)>,
+    /// The 1-based numbers and text of the context lines after this one
+    pub after_context: Vec<(usize, String)>,
+}
+
+impl fmt::Display for Match {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = self
+            .before_context
+            .iter()
+            .map(|(line_number, line)| (*line_number, line))
+            .chain([(self.line_number, &self.line)])
+            .chain(
+                self.after_context
+                    .iter()
+                    .map(|(line_number, line)| (*line_number, line)),
+            );
+        for (i, (line_number, line)) in lines.enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            if let Some(filename) = &self.filename {
+                write!(f, "{}:", filename)?;
+            }
+            write!(f, "{}: {}", line_number, line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a search could not be run.
+///
+/// ```
+/// use grep_lite::{GrepError, SearchConfig};
+///
+/// let result = SearchConfig::new("(unclosed").build();
+///
+/// assert!(matches!(result, Err(GrepError::Regex(_))));
+/// ```
+#[derive(Debug)]
+pub enum GrepError {
+    /// The input could not be opened or read.
+    Io(io::Error),
+    /// The pattern is not a valid regular expression.
+    Regex(regex::Error),
+    /// The pattern is not valid for PCRE2.
+    #[cfg(feature = "pcre2")]
+    Pcre2(pcre2::Error),
+    /// The search needs a feature this build was made without.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for GrepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrepError::Io(e) => write!(f, "{}", e),
+            GrepError::Regex(e) => write!(f, "{}", e),
+            #[cfg(feature = "pcre2")]
+            GrepError::Pcre2(e) => write!(f, "{}", e),
+            GrepError::Unsupported(what) => write!(f, "{}", what),
+        }
+    }
+}
+
+impl Error for GrepError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GrepError::Io(e) => Some(e),
+            GrepError::Regex(e) => Some(e),
+            #[cfg(feature = "pcre2")]
+            GrepError::Pcre2(e) => Some(e),
+            GrepError::Unsupported(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for GrepError {
+    fn from(e: io::Error) -> Self {
+        GrepError::Io(e)
+    }
+}
+
+impl From<regex::Error> for GrepError {
+    fn from(e: regex::Error) -> Self {
+        GrepError::Regex(e)
+    }
+}
+
+#[cfg(feature = "pcre2")]
+impl From<pcre2::Error> for GrepError {
+    fn from(e: pcre2::Error) -> Self {
+        GrepError::Pcre2(e)
+    }
+}
+
+/// Searches `reader` line by line, returning the matches found in order.
+///
+/// Nothing is returned for settings that only count, such as `count` or
+/// `quiet`. A read error part way through is returned as an error.
+///
+/// ```
+/// use grep_lite::{search_reader, SearchConfig};
+///
+/// let search = SearchConfig::new("error")
+///     .case_insensitive(true)
+///     .before_context(1)
+///     .build()
+///     .unwrap();
+/// let log = "starting\nloading config\nERROR: no config\n";
+/// let matches = search_reader(log.as_bytes(), &search).unwrap();
+///
+/// assert_eq!(matches.len(), 1);
+/// assert_eq!(matches[0].line_number, 3);
+/// assert_eq!(matches[0].before_context, [(2, "loading config".to_string())]);
+/// ```
+pub fn search_reader<R: BufRead>(
+    reader: R,
+    search: &CompiledSearch,
+) -> std::result::Result<Vec<Match>, GrepError> {
+    let mut sink = VecSink::default();
+    search_reader_with_sink(reader, search, &mut sink)?;
+    collect_matches(sink.events, search, None)
+}
+
+/// Searches the file at `path`, returning the matches found in order.
+///
+/// Compressed files and archives are searched the same way as by
+/// `grep-lite`, and large files are memory-mapped.
+///
+/// ```no_run
+/// use grep_lite::{search_file, SearchConfig};
+///
+/// let search = SearchConfig::new("TODO").build().unwrap();
+/// for found in search_file("src/main.rs", &search).unwrap() {
+///     println!("{}: {}", found.line_number, found.line);
+/// }
+/// ```
+pub fn search_file(
+    path: &str,
+    search: &CompiledSearch,
+) -> std::result::Result<Vec<Match>, GrepError> {
+    let mut sink = VecSink::default();
+    search_file_with_sink(path, search, &mut sink)?;
+    collect_matches(sink.events, search, Some(path))
+}
+
+// Gathers the lines from a search into matches, once it has finished
+fn collect_matches(
+    events: Vec<MatchEvent>,
+    search: &CompiledSearch,
+    filename: Option<&str>,
+) -> std::result::Result<Vec<Match>, GrepError> {
+    let mut collector = MatchCollector::new(search, filename);
+    for event in events {
+        collector.push(event)?;
+    }
+    collector.finish();
+    Ok(collector.ready.into())
+}
+
+// Groups the lines from a search into matches as they arrive, handing each
+// context line to the match it was printed for. A line between two matches
+// can be context for both, and is then given to each of them.
+struct MatchCollector<'a> {
+    search: &'a CompiledSearch,
+    filename: Option<String>,
+    after_context: usize,
+    // The unbroken run of context lines since the last match
+    context: Vec<(usize, String)>,
+    last_index: Option<usize>,
+    // The last match, held back until its after-context is complete
+    pending: Option<Match>,
+    // Matches that are complete, in the order they were found
+    ready: VecDeque<Match>,
+}
+
+impl<'a> MatchCollector<'a> {
+    fn new(search: &'a CompiledSearch, filename: Option<&str>) -> Self {
+        let config = &search.config;
+        MatchCollector {
+            search,
+            filename: filename.map(str::to_string),
+            after_context: if config.reports_every_line() {
+                0
+            } else {
+                config.after_context
+            },
+            context: Vec::new(),
+            last_index: None,
+            pending: None,
+            ready: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, event: MatchEvent) -> Result<()> {
+        match event {
+            MatchEvent::Line {
+                index,
+                offset,
+                line,
+            } => {
+                if !self.follows_last_line(index) {
+                    self.context.clear();
+                }
+                self.finish();
+                let config = &self.search.config;
+                let before = self.context.len().saturating_sub(config.before_context);
+                let match_ranges = if config.invert_match {
+                    Vec::new()
+                } else {
+                    self.search.pattern.regex.find_iter(&line).collect()
+                };
+                // A multiline match is followed by the line after its last
+                let last_index = if config.multiline {
+                    index + line.matches('\n').count()
+                } else {
+                    index
+                };
+                self.pending = Some(Match {
+                    filename: self.filename.clone(),
+                    line_number: index + 1,
+                    byte_offset: offset,
+                    line,
+                    match_ranges,
+                    before_context: self.context.split_off(before),
+                    after_context: Vec::new(),
+                });
+                self.context.clear();
+                self.last_index = Some(last_index);
+            }
+            MatchEvent::Context { index, line, .. } => {
+                if !self.follows_last_line(index) {
+                    self.context.clear();
+                    self.finish();
+                }
+                if let Some(pending) = &mut self.pending {
+                    pending.after_context.push((index + 1, line.clone()));
+                }
+                self.context.push((index + 1, line));
+                self.last_index = Some(index);
+            }
+            MatchEvent::Member(name) => {
+                self.finish();
+                self.filename = Some(name);
+                self.context.clear();
+                self.last_index = None;
+            }
+            MatchEvent::ReadError(e) => {
+                self.finish();
+                return Err(io::Error::other(e));
+            }
+            // A match only has the line's text
+            MatchEvent::LineBytes(_)
+            | MatchEvent::Count(_)
+            | MatchEvent::FileMatched
+            | MatchEvent::Stats(_) => {}
+        }
+
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| pending.after_context.len() >= self.after_context)
+        {
+            self.finish();
+        }
+        Ok(())
+    }
+
+    fn follows_last_line(&self, index: usize) -> bool {
+        self.last_index.is_some_and(|last| last + 1 == index)
+    }
+
+    // Hands over the last match, as nothing more can be added to it
+    fn finish(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            self.ready.push_back(pending);
+        }
+    }
+}
+
+/// An iterator over the matches in a reader, searching only as far as is
+/// needed to return the next one. Made by [`CompiledSearch::search`].
+///
+/// Context lines are bundled into each match, so a match is only returned
+/// once its after-context has been read. A read error ends the iteration.
+pub struct Matches<'a, R: BufRead> {
+    reader: R,
+    lines: LineSearch<'a>,
+    // Reused for every line, as in `process_line`
+    line: String,
+    error: Option<io::Error>,
+    done: bool,
+}
+
+impl<R: BufRead> Matches<'_, R> {
+    // Feeds the next line to the search, or the whole input for a multiline
+    // search, collecting any matches it completes
+    fn read_more(&mut self) {
+        let result = if self.lines.matcher.multiline {
+            self.done = true;
+            let mut data = Vec::new();
+            (&mut self.reader)
+                .take(MULTILINE_SIZE_LIMIT + 1)
+                .read_to_end(&mut data)
+                .and_then(|_| self.lines.feed_text(&data))
+        } else {
+            self.line.clear();
+            let terminator = self.lines.matcher.terminator;
+            match read_line_until(&mut self.reader, terminator, &mut self.line, &mut None) {
+                Ok(0) => {
+                    self.done = true;
+                    Ok(())
+                }
+                Ok(read) => self.lines.feed_line(&mut self.line, read),
+                Err(e) => Err(e),
+            }
+        };
+
+        if let Err(e) = result {
+            self.error = Some(e);
+            self.done = true;
+        }
+        self.done |= self.lines.matcher.is_done();
+        if self.done {
+            self.lines.collector.finish();
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Matches<'_, R> {
+    type Item = Result<Match>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(found) = self.lines.collector.ready.pop_front() {
+                return Some(Ok(found));
+            }
+            if self.done {
+                return self.error.take().map(Err);
+            }
+            self.read_more();
+        }
+    }
+}
+
+/// Searches `reader` without blocking, returning the matches found in order.
+/// Available with the `async-tokio` feature.
+///
+/// ```no_run
+/// use grep_lite::{search_file_async, search_reader_async, SearchConfig};
+/// use std::path::Path;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let search = SearchConfig::new("ERROR").build().unwrap();
+/// let found = search_reader_async(&b"ok\nERROR: disk full\n"[..], &search).await?;
+/// assert_eq!(found[0].line_number, 2);
+///
+/// for found in search_file_async(Path::new("app.log"), &search).await? {
+///     println!("{}", found);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "async-tokio")]
+pub async fn search_reader_async<R: AsyncBufRead + Unpin>(
+    reader: R,
+    search: &CompiledSearch,
+) -> Result<Vec<Match>> {
+    search_lines_async(reader, search, None).await
+}
+
+/// Searches the file at `path` without blocking, returning the matches found
+/// in order. Available with the `async-tokio` feature.
+///
+/// The file is read as plain text: unlike [`search_file`], compressed files
+/// and archives are not looked inside.
+#[cfg(feature = "async-tokio")]
+pub async fn search_file_async(path: &Path, search: &CompiledSearch) -> Result<Vec<Match>> {
+    let file = tokio::fs::File::open(path).await?;
+    let reader = tokio::io::BufReader::new(file);
+    search_lines_async(reader, search, Some(&path.to_string_lossy())).await
+}
+
+// Reads `reader` a line at a time into a `LineSearch`, the same way
+// `Matches` does
+#[cfg(feature = "async-tokio")]
+async fn search_lines_async<R: AsyncBufRead + Unpin>(
+    mut reader: R,
+    search: &CompiledSearch,
+    filename: Option<&str>,
+) -> Result<Vec<Match>> {
+    let mut lines = LineSearch::new(search, filename);
+    if lines.matcher.multiline {
+        let mut data = Vec::new();
+        (&mut reader)
+            .take(MULTILINE_SIZE_LIMIT + 1)
+            .read_to_end(&mut data)
+            .await?;
+        lines.feed_text(&data)?;
+    } else {
+        let mut line = String::new();
+        let terminator = lines.matcher.terminator;
+        while !lines.matcher.is_done() {
+            line.clear();
+            let mut bytes = std::mem::take(&mut line).into_bytes();
+            let read = reader.read_until(terminator, &mut bytes).await?;
+            decode_line(bytes, &mut line);
+            if read == 0 {
+                break;
+            }
+            lines.feed_line(&mut line, read)?;
+        }
+    }
+    lines.collector.finish();
+    Ok(lines.collector.ready.into())
+}
+
+// A search that is handed its input a line at a time and groups what it
+// finds into matches, for callers that do their own reading
+struct LineSearch<'a> {
+    matcher: LineMatcher<'a>,
+    collector: MatchCollector<'a>,
+    // Events from the lines fed in so far that haven't been collected yet
+    events: VecSink,
+    index: usize,
+    offset: u64,
+}
+
+impl<'a> LineSearch<'a> {
+    fn new(search: &'a CompiledSearch, filename: Option<&str>) -> Self {
+        LineSearch {
+            matcher: LineMatcher::new(&search.pattern, &search.config),
+            collector: MatchCollector::new(search, filename),
+            events: VecSink::default(),
+            index: 0,
+            offset: 0,
+        }
+    }
+
+    // Takes the next line, as read with its line ending in `read` bytes
+    fn feed_line(&mut self, line: &mut String, read: usize) -> Result<()> {
+        let offset = self.offset;
+        self.offset += read as u64;
+        trim_line_terminator(line, self.matcher.terminator);
+        self.matcher
+            .feed(self.index, offset, line, None, &mut self.events);
+        self.index += 1;
+        self.collect()
+    }
+
+    // Takes the whole input at once, for a multiline search
+    fn feed_text(&mut self, data: &[u8]) -> Result<()> {
+        search_multiline(data, &mut self.matcher, &mut self.events);
+        self.collect()
+    }
+
+    fn collect(&mut self) -> Result<()> {
+        for event in self.events.events.drain(..) {
+            self.collector.push(event)?;
+        }
+        Ok(())
+    }
+}
+
+/// Searches `reader` line by line, reporting what to show through `sink` and
+/// returning how many lines were counted.
+pub fn search_reader_with_sink<R: BufRead>(
+    reader: R,
+    search: &CompiledSearch,
+    sink: &mut dyn Sink,
+) -> Result<usize> {
+    let matcher = LineMatcher::new(&search.pattern, &search.config);
+    process_line(reader, matcher, sink)
+}
+
+/// Rewrites the file at `path` with every match on its matching lines
+/// replaced by `replacement`, returning how many lines matched.
+///
+/// The new contents are written to a temporary file beside the original and
+/// then renamed over it, so a crash never leaves the file half written. A
+/// symlink is left in place, and the file it points to is edited. Line
+/// endings, including whether the last line has one, are kept as they were.
+/// With `backup_suffix`, the original is first copied to `path` plus the
+/// suffix. Files whose contents would not change are left alone.
+///
+/// Only regular, uncompressed files can be edited, and lines that are not
+/// valid UTF-8 are never changed.
+///
+/// ```
+/// use grep_lite::{replace_in_file, SearchConfig};
+///
+/// let path = std::env::temp_dir().join("grep-lite-replace-in-file.txt");
+/// std::fs::write(&path, "cat\ndog\ncat cat\n").unwrap();
+/// let path = path.to_str().unwrap();
+/// let search = SearchConfig::new("cat").build().unwrap();
+///
+/// assert_eq!(replace_in_file(path, &search, "cow", None).unwrap(), 2);
+/// assert_eq!(std::fs::read_to_string(path).unwrap(), "cow\ndog\ncow cow\n");
+/// # std::fs::remove_file(path).unwrap();
+/// ```
+pub fn replace_in_file(
+    path: &str,
+    search: &CompiledSearch,
+    replacement: &str,
+    backup_suffix: Option<&str>,
+) -> Result<usize> {
+    let edit = edit_file(path, search, replacement)?;
+    if edit.changed == 0 {
+        return Ok(edit.matched);
+    }
+
+    if let Some(suffix) = backup_suffix {
+        fs::copy(path, format!("{}{}", path, suffix))?;
+    }
+    // Renaming over a symlink would replace it, so the file it points to is
+    // the one replaced
+    let path = fs::canonicalize(path)?;
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{}.grep-lite-tmp", file_name));
+    let result = write_file(&temp_path, &edit.contents, edit.permissions)
+        .and_then(|()| fs::rename(&temp_path, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.map(|()| edit.matched)
+}
+
+/// Counts the lines [`replace_in_file`] would change in the file at `path`,
+/// without writing anything. Lines that match but are the same once
+/// replaced aren't counted.
+///
+/// ```
+/// use grep_lite::{count_replaced_lines, SearchConfig};
+///
+/// let path = std::env::temp_dir().join("grep-lite-count-replaced-lines.txt");
+/// std::fs::write(&path, "cat\ndog\ncow\n").unwrap();
+/// let path = path.to_str().unwrap();
+/// let search = SearchConfig::new("c[ao]").build().unwrap();
+///
+/// // `cow` matches, but is already what it would be replaced with
+/// assert_eq!(count_replaced_lines(path, &search, "co").unwrap(), 1);
+/// assert_eq!(std::fs::read_to_string(path).unwrap(), "cat\ndog\ncow\n");
+/// # std::fs::remove_file(path).unwrap();
+/// ```
+pub fn count_replaced_lines(
+    path: &str,
+    search: &CompiledSearch,
+    replacement: &str,
+) -> Result<usize> {
+    edit_file(path, search, replacement).map(|edit| edit.changed)
+}
+
+/// Reads the file at `path` and returns its contents as they are and as
+/// [`replace_in_file`] would write them, without writing anything.
+///
+/// ```
+/// use grep_lite::{replaced_file_contents, SearchConfig};
+///
+/// let path = std::env::temp_dir().join("grep-lite-replaced-file-contents.txt");
+/// std::fs::write(&path, "cat\ndog\n").unwrap();
+/// let search = SearchConfig::new("cat").build().unwrap();
+///
+/// let (before, after) = replaced_file_contents(path.to_str().unwrap(), &search, "cow").unwrap();
+/// assert_eq!(before, b"cat\ndog\n");
+/// assert_eq!(after, b"cow\ndog\n");
+/// # std::fs::remove_file(path).unwrap();
+/// ```
+pub fn replaced_file_contents(
+    path: &str,
+    search: &CompiledSearch,
+    replacement: &str,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    edit_file(path, search, replacement).map(|edit| (edit.original, edit.contents))
+}
+
+// A file's contents with `--replace` applied, for `replace_in_file`
+struct FileEdit {
+    original: Vec<u8>,
+    contents: Vec<u8>,
+    permissions: fs::Permissions,
+    // Lines with a match, and those of them that came out different
+    matched: usize,
+    changed: usize,
+}
+
+// Reads the file at `path` and applies `replacement` to its matching lines
+fn edit_file(path: &str, search: &CompiledSearch, replacement: &str) -> Result<FileEdit> {
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_file() {
+        return Err(io::Error::other("not a regular file"));
+    }
+    let regex = search
+        .pattern
+        .regex
+        .as_regex()
+        .ok_or_else(|| io::Error::other("replacing isn't supported with PCRE2"))?;
+    let data = fs::read(path)?;
+    if detect_compression(path, &data, &search.config) != Compression::None {
+        return Err(io::Error::other(
+            "compressed files can't be edited in place",
+        ));
+    }
+
+    let terminator = search.config.line_terminator();
+    let mut contents = Vec::with_capacity(data.len());
+    let mut matched = 0;
+    let mut changed = 0;
+    for line in data.split_inclusive(|&byte| byte == terminator) {
+        let content = match line.strip_suffix(&[terminator]) {
+            Some(content) if terminator == b'\n' => content.strip_suffix(b"\r").unwrap_or(content),
+            Some(content) => content,
+            None => line,
+        };
+        match std::str::from_utf8(content) {
+            Ok(text) if search.pattern.is_match(text) => {
+                matched += 1;
+                let replaced = regex.replace_all(text, replacement);
+                if replaced != text {
+                    changed += 1;
+                }
+                contents.extend_from_slice(replaced.as_bytes());
+                contents.extend_from_slice(&line[content.len()..]);
+            }
+            _ => contents.extend_from_slice(line),
+        }
+    }
+    Ok(FileEdit {
+        original: data,
+        contents,
+        permissions: metadata.permissions(),
+        matched,
+        changed,
+    })
+}
+
+// Writes `data` to a new file and waits for it to reach the disk
+fn write_file(path: &Path, data: &[u8], permissions: fs::Permissions) -> Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(data)?;
+    file.set_permissions(permissions)?;
+    file.sync_all()
+}
+
+// Files bigger than this are not searched with `--multiline`, which needs
+// the whole file in memory
+const MULTILINE_SIZE_LIMIT: u64 = 256 * 1024 * 1024;
+
+/// Files at least this big are memory-mapped by default, and smaller ones
+/// are read whole when only counting. It's the default
+/// [`SearchConfig::mmap_threshold`].
+///
+/// ```
+/// use grep_lite::{SearchConfig, MMAP_THRESHOLD};
+///
+/// assert_eq!(SearchConfig::default().mmap_threshold, MMAP_THRESHOLD);
+/// ```
+pub const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// A compiled pattern, from the regex crate unless [`SearchConfig::pcre2`]
+/// asked for PCRE2. Matches are given as byte ranges into the text searched.
+///
+/// ```
+/// use grep_lite::SearchConfig;
+///
+/// let search = SearchConfig::new("o+").build().unwrap();
+/// let found: Vec<_> = search.regex().find_iter("foo boo").collect();
+///
+/// assert_eq!(found, vec![1..3, 5..7]);
+/// ```
+#[derive(Debug, Clone)]
+pub enum CompiledRegex {
+    /// Compiled by the regex crate
+    Regex(Regex),
+    /// Compiled by PCRE2. A match that fails, such as by hitting PCRE2's
+    /// backtracking limit, counts as no match.
+    #[cfg(feature = "pcre2")]
+    Pcre2(pcre2::bytes::Regex),
+}
+
+impl CompiledRegex {
+    /// Whether the pattern matches anywhere in `text`.
+    ///
+    /// ```
+    /// use grep_lite::SearchConfig;
+    ///
+    /// let search = SearchConfig::new("o+").build().unwrap();
+    ///
+    /// assert!(search.regex().is_match("foo"));
+    /// assert!(!search.regex().is_match("bar"));
+    /// ```
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            CompiledRegex::Regex(regex) => regex.is_match(text),
+            #[cfg(feature = "pcre2")]
+            CompiledRegex::Pcre2(regex) => regex.is_match(text.as_bytes()).unwrap_or(false),
+        }
+    }
+
+    /// The first match in `text`.
+    pub fn find(&self, text: &str) -> Option<Range<usize>> {
+        self.find_at(text, 0)
+    }
+
+    /// The first match in `text` starting at or after `start`. The text
+    /// before `start` is still looked at for anchors such as `\b`.
+    ///
+    /// ```
+    /// use grep_lite::SearchConfig;
+    ///
+    /// let search = SearchConfig::new(r"\bcat").build().unwrap();
+    ///
+    /// assert_eq!(search.regex().find_at("cat concat cat", 1), Some(11..14));
+    /// ```
+    pub fn find_at(&self, text: &str, start: usize) -> Option<Range<usize>> {
+        match self {
+            CompiledRegex::Regex(regex) => regex.find_at(text, start).map(|found| found.range()),
+            #[cfg(feature = "pcre2")]
+            CompiledRegex::Pcre2(regex) => regex
+                .find_at(text.as_bytes(), start)
+                .ok()
+                .flatten()
+                .map(|found| found.start()..found.end()),
+        }
+    }
+
+    /// Every match in `text`, in order and without overlapping.
+    pub fn find_iter<'t>(&'t self, text: &'t str) -> Box<dyn Iterator<Item = Range<usize>> + 't> {
+        match self {
+            CompiledRegex::Regex(regex) => {
+                Box::new(regex.find_iter(text).map(|found| found.range()))
+            }
+            #[cfg(feature = "pcre2")]
+            CompiledRegex::Pcre2(regex) => Box::new(
+                regex
+                    .find_iter(text.as_bytes())
+                    .map_while(|found| found.ok())
+                    .map(|found| found.start()..found.end()),
+            ),
+        }
+    }
+
+    /// The regex crate's regex, for capture groups and replacing. There is
+    /// none with PCRE2.
+    ///
+    /// ```
+    /// use grep_lite::SearchConfig;
+    ///
+    /// let search = SearchConfig::new(r"(\w+)@(\w+)").build().unwrap();
+    /// let regex = search.regex().as_regex().unwrap();
+    ///
+    /// assert_eq!(regex.replace("me@home", "$2/$1"), "home/me");
+    /// ```
+    pub fn as_regex(&self) -> Option<&Regex> {
+        match self {
+            CompiledRegex::Regex(regex) => Some(regex),
+            #[cfg(feature = "pcre2")]
+            CompiledRegex::Pcre2(_) => None,
+        }
+    }
+}
+
+// A compiled regex plus, when one can be found, a literal that every match
+// has to contain. Looking for the literal with memmem is much cheaper than
+// running the regex, so most non-matching lines never reach the regex engine.
+#[derive(Debug)]
+struct Pattern {
+    regex: CompiledRegex,
+    // Each pattern on its own, when `regex` combines more than one
+    regexes: Vec<CompiledRegex>,
+    literal: Option<Finder<'static>>,
+    ignore_case: bool,
+    unicode: bool,
+    regex_size_limit: Option<usize>,
+    dfa_size_limit: Option<usize>,
+    buffer_regex: OnceLock<Option<Regex>>,
+}
+
+impl Pattern {
+    // Compiles the patterns of `config`, with its case and multiline
+    // settings. Several patterns are combined into one regex matching any
+    // of them.
+    fn new(config: &SearchConfig) -> std::result::Result<Self, GrepError> {
+        let patterns: Vec<String> = std::iter::once(&config.pattern)
+            .chain(&config.extra_patterns)
+            .map(|pattern| {
+                if config.fixed_strings {
+                    regex::escape(pattern)
+                } else {
+                    pattern.clone()
+                }
+            })
+            .collect();
+        let pattern = if patterns.len() == 1 {
+            patterns[0].clone()
+        } else {
+            let alternatives: Vec<String> = patterns
+                .iter()
+                .map(|pattern| format!("(?:{})", pattern))
+                .collect();
+            alternatives.join("|")
+        };
+        let ignore_case = is_case_insensitive(config, &pattern);
+        let build = |pattern: &str| -> std::result::Result<CompiledRegex, GrepError> {
+            if config.pcre2 {
+                return build_pcre2(pattern, config, ignore_case);
+            }
+            let mut builder = RegexBuilder::new(pattern);
+            builder
+                .case_insensitive(ignore_case)
+                .multi_line(config.multiline)
+                .crlf(config.multiline)
+                .dot_matches_new_line(config.multiline && config.multiline_dotall)
+                .unicode(config.unicode);
+            set_size_limits(&mut builder, config.regex_size_limit, config.dfa_size_limit);
+            Ok(CompiledRegex::Regex(builder.build()?))
+        };
+        let regex = build(&pattern)?;
+        let regexes = if patterns.len() == 1 {
+            vec![regex.clone()]
+        } else {
+            patterns
+                .iter()
+                .map(|pattern| build(pattern))
+                .collect::<std::result::Result<_, _>>()?
+        };
+
+        // Case-insensitive letters parse to classes rather than literals,
+        // so only text that is the same in every case is looked for. PCRE2
+        // patterns are left to PCRE2.
+        let literal = regex
+            .as_regex()
+            .and_then(|regex| {
+                ParserBuilder::new()
+                    .case_insensitive(ignore_case)
+                    .build()
+                    .parse(regex.as_str())
+                    .ok()
+            })
+            .and_then(|hir| required_literal(&hir))
+            .map(|literal| Finder::new(&literal).into_owned());
+        Ok(Pattern {
+            regex,
+            regexes,
+            literal,
+            ignore_case,
+            unicode: config.unicode,
+            regex_size_limit: config.regex_size_limit,
+            dfa_size_limit: config.dfa_size_limit,
+            buffer_regex: OnceLock::new(),
+        })
+    }
+
+    // The same pattern compiled to search a whole buffer of lines at once.
+    // Patterns that anchor to the start or end of the text can't be run
+    // this way, since each line is its own text when searched one by one,
+    // and neither can PCRE2 patterns.
+    fn buffer_regex(&self) -> Option<&Regex> {
+        self.buffer_regex
+            .get_or_init(|| {
+                let pattern = self.regex.as_regex()?.as_str();
+                if pattern.contains("\\A") || pattern.contains("\\z") {
+                    return None;
+                }
+                let mut builder = RegexBuilder::new(pattern);
+                builder
+                    .case_insensitive(self.ignore_case)
+                    .multi_line(true)
+                    .crlf(true)
+                    .unicode(self.unicode);
+                set_size_limits(&mut builder, self.regex_size_limit, self.dfa_size_limit);
+                builder.build().ok()
+            })
+            .as_ref()
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        if let Some(literal) = &self.literal {
+            if literal.find(line.as_bytes()).is_none() {
+                return false;
+            }
+        }
+        self.regex.is_match(line)
+    }
+}
+
+// Raises or lowers the regex crate's limits on how big a compiled regex and
+// its lazy DFA may grow, where they're given
+fn set_size_limits(
+    builder: &mut RegexBuilder,
+    regex_size_limit: Option<usize>,
+    dfa_size_limit: Option<usize>,
+) {
+    if let Some(limit) = regex_size_limit {
+        builder.size_limit(limit);
+    }
+    if let Some(limit) = dfa_size_limit {
+        builder.dfa_size_limit(limit);
+    }
+}
+
+// Compiles `pattern` with PCRE2, with the same settings the regex crate
+// would be given
+#[cfg(feature = "pcre2")]
+fn build_pcre2(
+    pattern: &str,
+    config: &SearchConfig,
+    ignore_case: bool,
+) -> std::result::Result<CompiledRegex, GrepError> {
+    let regex = pcre2::bytes::RegexBuilder::new()
+        .caseless(ignore_case)
+        .multi_line(config.multiline)
+        .crlf(config.multiline)
+        .dotall(config.multiline && config.multiline_dotall)
+        .utf(true)
+        .ucp(config.unicode)
+        .jit_if_available(true)
+        .build(pattern)?;
+    Ok(CompiledRegex::Pcre2(regex))
+}
+
+#[cfg(not(feature = "pcre2"))]
+fn build_pcre2(
+    _pattern: &str,
+    _config: &SearchConfig,
+    _ignore_case: bool,
+) -> std::result::Result<CompiledRegex, GrepError> {
+    Err(GrepError::Unsupported(
+        "PCRE2 isn't available in this build; enable the pcre2 feature",
+    ))
+}
+
+// Returns the longest literal that every match of the pattern has to
+// contain, if there is one. Only concatenations, groups and repetitions that
+// happen at least once are looked into; alternations rarely share a literal.
+fn required_literal(hir: &Hir) -> Option<Vec<u8>> {
+    match hir.kind() {
+        HirKind::Literal(literal) => Some(literal.0.to_vec()),
+        HirKind::Capture(capture) => required_literal(&capture.sub),
+        HirKind::Repetition(repetition) if repetition.min > 0 => required_literal(&repetition.sub),
+        HirKind::Concat(subs) => {
+            let mut longest = None;
+            let mut run = Vec::new();
+            for sub in subs {
+                if let HirKind::Literal(literal) = sub.kind() {
+                    run.extend_from_slice(&literal.0);
+                    continue;
+                }
+                keep_longest(&mut longest, std::mem::take(&mut run));
+                if let Some(literal) = required_literal(sub) {
+                    keep_longest(&mut longest, literal);
+                }
+            }
+            keep_longest(&mut longest, run);
+            longest
+        }
+        _ => None,
+    }
+}
+
+fn keep_longest(longest: &mut Option<Vec<u8>>, literal: Vec<u8>) {
+    if literal.len() > longest.as_ref().map_or(0, Vec::len) {
+        *longest = Some(literal);
+    }
+}
+
+// `-i` always makes the search case-insensitive and `--case-sensitive` makes
+// it sensitive. Otherwise `--smart-case` ignores case for all-lowercase
+// patterns.
+fn is_case_insensitive(config: &SearchConfig, pattern: &str) -> bool {
+    if config.ignore_case {
+        true
+    } else if config.case_sensitive || !config.smart_case {
+        false
+    } else {
+        // A pattern that doesn't parse fails to build later anyway
+        ast::parse::Parser::new()
+            .parse(pattern)
+            .is_ok_and(|ast| !has_uppercase(&ast))
+    }
+}
+
+// Whether any character written out in the pattern is uppercase. Escaped
+// characters are skipped, so `\S` or `\x41` don't turn off smart-case.
+fn has_uppercase(ast: &Ast) -> bool {
+    match ast {
+        Ast::Literal(literal) => is_uppercase_literal(literal),
+        Ast::ClassBracketed(class) => class_has_uppercase(&class.kind),
+        Ast::Repetition(repetition) => has_uppercase(&repetition.ast),
+        Ast::Group(group) => has_uppercase(&group.ast),
+        Ast::Alternation(alternation) => alternation.asts.iter().any(has_uppercase),
+        Ast::Concat(concat) => concat.asts.iter().any(has_uppercase),
+        _ => false,
+    }
+}
+
+fn class_has_uppercase(set: &ClassSet) -> bool {
+    match set {
+        ClassSet::Item(item) => class_item_has_uppercase(item),
+        ClassSet::BinaryOp(op) => class_has_uppercase(&op.lhs) || class_has_uppercase(&op.rhs),
+    }
+}
+
+fn class_item_has_uppercase(item: &ClassSetItem) -> bool {
+    match item {
+        ClassSetItem::Literal(literal) => is_uppercase_literal(literal),
+        ClassSetItem::Range(range) => {
+            is_uppercase_literal(&range.start) || is_uppercase_literal(&range.end)
+        }
+        ClassSetItem::Bracketed(class) => class_has_uppercase(&class.kind),
+        ClassSetItem::Union(union) => union.items.iter().any(class_item_has_uppercase),
+        _ => false,
+    }
+}
+
+fn is_uppercase_literal(literal: &ast::Literal) -> bool {
+    literal.kind == LiteralKind::Verbatim && literal.c.is_uppercase()
+}
+
+/// Something produced by a search that may need to be shown to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MatchEvent {
+    /// A matching line to print, `offset` bytes into the input.
+    Line {
+        index: usize,
+        offset: u64,
+        line: String,
+    },
+    /// A line of context around a match, `offset` bytes into the input.
+    Context {
+        index: usize,
+        offset: u64,
+        line: String,
+    },
+    /// The line of the next `Line` or `Context` event isn't valid UTF-8, so
+    /// its text has each invalid sequence replaced by U+FFFD. These are its
+    /// bytes as read, without the line ending.
+    LineBytes(Vec<u8>),
+    /// The number of counted lines, emitted once at the end when counting.
+    Count(usize),
+    /// The input could not be read any further.
+    ReadError(String),
+    /// The events that follow belong to this file inside an archive.
+    Member(String),
+    /// The input had at least one match, for `--files-with-matches`.
+    FileMatched,
+    /// How much of the input was searched and what was found in it, emitted
+    /// last when [`SearchConfig::stats`] is set. Each file in an archive
+    /// gets its own.
+    Stats(SearchStats),
+}
+
+/// What a search found in one input, and how much of it was read. Searches
+/// that stop early, such as for `max_count`, only count what they got to.
+///
+/// ```
+/// use grep_lite::{search_reader_with_sink, MatchEvent, SearchConfig, SearchStats, VecSink};
+///
+/// let search = SearchConfig {
+///     pattern: "b+".to_string(),
+///     stats: true,
+///     ..SearchConfig::default()
+/// };
+/// let search = grep_lite::CompiledSearch::new(search).unwrap();
+/// let mut sink = VecSink::default();
+/// search_reader_with_sink(&b"a\nbb b\nc\n"[..], &search, &mut sink).unwrap();
+///
+/// let expected = SearchStats {
+///     matched_lines: 1,
+///     matches: 2,
+///     lines_searched: 3,
+///     bytes_searched: 9,
+/// };
+/// assert_eq!(sink.events.last(), Some(&MatchEvent::Stats(expected)));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// The number of matching lines, or of lines found by `invert_match`
+    pub matched_lines: usize,
+    /// The number of matches on those lines. Each line found by
+    /// `invert_match` counts as one.
+    pub matches: usize,
+    /// How many lines of the input were searched
+    pub lines_searched: usize,
+    /// How many bytes of the input were searched, after any decompression
+    pub bytes_searched: u64,
+}
+
+impl AddAssign for SearchStats {
+    fn add_assign(&mut self, other: Self) {
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+        self.lines_searched += other.lines_searched;
+        self.bytes_searched += other.bytes_searched;
+    }
+}
+
+/// Receives the events produced by a search, in the order they happen.
+///
+/// ```
+/// use grep_lite::{search_reader_with_sink, MatchEvent, SearchConfig, Sink};
+///
+/// // Prints the number of each matching line as soon as it is found
+/// struct LineNumbers;
+///
+/// impl Sink for LineNumbers {
+///     fn emit(&mut self, event: MatchEvent) {
+///         if let MatchEvent::Line { index, .. } = event {
+///             println!("{}", index + 1);
+///         }
+///     }
+/// }
+///
+/// let search = SearchConfig::new("b").build().unwrap();
+/// let count = search_reader_with_sink(&b"a\nb\nab\n"[..], &search, &mut LineNumbers);
+///
+/// assert_eq!(count.unwrap(), 2);
+/// ```
+pub trait Sink {
+    fn emit(&mut self, event: MatchEvent);
+}
+
+/// Keeps events in memory so they can be inspected or replayed later.
+///
+/// ```
+/// use grep_lite::{search_reader_with_sink, MatchEvent, SearchConfig, VecSink};
+///
+/// let search = SearchConfig::new("b").build().unwrap();
+/// let mut sink = VecSink::default();
+/// search_reader_with_sink(&b"a\nb\n"[..], &search, &mut sink).unwrap();
+///
+/// assert_eq!(
+///     sink.events,
+///     [MatchEvent::Line {
+///         index: 1,
+///         offset: 2,
+///         line: "b".to_string()
+///     }]
+/// );
+/// ```
+#[derive(Debug, Default)]
+pub struct VecSink {
+    pub events: Vec<MatchEvent>,
+}
+
+impl Sink for VecSink {
+    fn emit(&mut self, event: MatchEvent) {
+        self.events.push(event);
+    }
+}
+
+/// Searches a single file, reporting what to show through `sink` and
+/// returning how many lines were counted.
+///
+/// ```
+/// use grep_lite::{search_file_with_sink, SearchConfig, VecSink};
+///
+/// let path = std::env::temp_dir().join("grep-lite-search-file-with-sink.txt");
+/// std::fs::write(&path, "a\nb\nab\n").unwrap();
+/// let search = SearchConfig::new("a").build().unwrap();
+/// let mut sink = VecSink::default();
+///
+/// let count = search_file_with_sink(path.to_str().unwrap(), &search, &mut sink).unwrap();
+/// assert_eq!(count, 2);
+/// assert_eq!(sink.events.len(), 2);
+/// # std::fs::remove_file(path).unwrap();
+/// ```
+pub fn search_file_with_sink(
+    file_name: &str,
+    search: &CompiledSearch,
+    sink: &mut dyn Sink,
+) -> Result<usize> {
+    let CompiledSearch {
+        config, pattern, ..
+    } = search;
+    let matcher = LineMatcher::new(pattern, config);
+
+    let file = File::open(file_name)?;
+    let metadata = file.metadata()?;
+    if let Some(map) = map_file(&file, &metadata, config) {
+        if detect_compression(file_name, &map, config) == Compression::None {
+            return Ok(process_mmap(&map, matcher, sink));
+        }
+    }
+
+    let is_small = metadata.len() < config.mmap_threshold;
+    let mut reader = BufReader::new(file);
+    match detect_compression(file_name, reader.fill_buf()?, config) {
+        // Counting doesn't need lines one at a time, so the whole file can be
+        // searched at once
+        Compression::None if matcher.is_summary_only() && is_small => {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            Ok(process_mmap(&data, matcher, sink))
+        }
+        Compression::Zip => search_zip(reader, file_name, search, sink),
+        compression => {
+            let Some(reader) = decompress_reader(reader, file_name, compression)? else {
+                return Ok(0);
+            };
+            process_line(reader, matcher, sink)
+        }
+    }
+}
+
+// Searches every file inside a zip archive that passes the search's member
+// filter, naming each `archive.zip!/path/in/zip`
+fn search_zip(
+    reader: BufReader<File>,
+    file_name: &str,
+    search: &CompiledSearch,
+    sink: &mut dyn Sink,
+) -> Result<usize> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut total = 0;
+
+    for i in 0..archive.len() {
+        let member = archive.by_index(i)?;
+        if !member.is_file() {
+            continue;
+        }
+        let name = format!("{}!/{}", file_name, member.name());
+        if let Some(filter) = &search.member_filter {
+            if !filter(Path::new(&name)) {
+                continue;
+            }
+        }
+        sink.emit(MatchEvent::Member(name));
+        let matcher = LineMatcher::new(&search.pattern, &search.config);
+        total += process_line(BufReader::new(member), matcher, sink)?;
+    }
+
+    Ok(total)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    Lzma,
+    Zip,
+}
+
+// Works out how a file is compressed, trying its extension first and then
+// its first few bytes. Only gzip and zip are sniffed by default; `--decompress`
+// also sniffs bzip2 and xz. Anything else is plain text.
+fn detect_compression(path: &str, header: &[u8], config: &SearchConfig) -> Compression {
+    if config.zip {
+        return Compression::Zip;
+    }
+    match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Compression::Gzip,
+        Some("bz2") => Compression::Bzip2,
+        Some("xz") => Compression::Xz,
+        Some("lzma") => Compression::Lzma,
+        Some("zip") => Compression::Zip,
+        _ if header.starts_with(GZIP_MAGIC) => Compression::Gzip,
+        _ if header.starts_with(ZIP_MAGIC) => Compression::Zip,
+        _ if config.decompress && header.starts_with(BZIP2_MAGIC) => Compression::Bzip2,
+        _ if config.decompress && header.starts_with(XZ_MAGIC) => Compression::Xz,
+        _ => Compression::None,
+    }
+}
+
+// Wraps a file in the decoder for its compression format. Returns `None` when
+// the file should be skipped.
+fn decompress_reader(
+    reader: BufReader<File>,
+    path: &str,
+    compression: Compression,
+) -> Result<Option<Box<dyn BufRead>>> {
+    match compression {
+        Compression::None | Compression::Zip => Ok(Some(Box::new(reader))),
+        Compression::Gzip => Ok(Some(Box::new(BufReader::new(GzDecoder::new(reader))))),
+        Compression::Bzip2 => Ok(bzip2_reader(reader, path)),
+        Compression::Xz => xz_reader(reader, path, false),
+        Compression::Lzma => xz_reader(reader, path, true),
+    }
+}
+
+#[cfg(feature = "bzip2")]
+fn bzip2_reader(reader: BufReader<File>, _path: &str) -> Option<Box<dyn BufRead>> {
+    Some(Box::new(BufReader::new(bzip2::read::BzDecoder::new(
+        reader,
+    ))))
+}
+
+#[cfg(not(feature = "bzip2"))]
+fn bzip2_reader(_reader: BufReader<File>, path: &str) -> Option<Box<dyn BufRead>> {
+    eprintln!(
+        "grep-lite: {}: skipping bzip2 file, built without the `bzip2` feature",
+        path
+    );
+    None
+}
+
+// `.lzma` files use the legacy format, which needs its own decoder
+#[cfg(feature = "xz")]
+fn xz_reader(reader: BufReader<File>, _path: &str, lzma: bool) -> Result<Option<Box<dyn BufRead>>> {
+    let decoder = if lzma {
+        let stream = xz2::stream::Stream::new_lzma_decoder(u64::MAX).map_err(io::Error::other)?;
+        xz2::read::XzDecoder::new_stream(reader, stream)
+    } else {
+        xz2::read::XzDecoder::new(reader)
+    };
+    Ok(Some(Box::new(BufReader::new(decoder))))
+}
+
+#[cfg(not(feature = "xz"))]
+fn xz_reader(
+    _reader: BufReader<File>,
+    path: &str,
+    _lzma: bool,
+) -> Result<Option<Box<dyn BufRead>>> {
+    eprintln!(
+        "grep-lite: {}: skipping xz file, built without the `xz` feature",
+        path
+    );
+    Ok(None)
+}
+
+// Memory-maps a regular file when `--mmap` asks for it or, by default, when
+// it is large enough to be worth it. Returns `None` for anything that can't
+// be mapped (pipes, special files, empty files), which should be read normally.
+fn map_file(file: &File, metadata: &fs::Metadata, config: &SearchConfig) -> Option<Mmap> {
+    if config.no_mmap {
+        return None;
+    }
+    if !metadata.is_file() || metadata.len() == 0 {
+        return None;
+    }
+    if !config.mmap && metadata.len() < config.mmap_threshold {
+        return None;
+    }
+    // Safety: the map is only read from. If another process truncates the
+    // file while we search it we may fault, which is the trade-off of mmap.
+    unsafe { Mmap::map(file) }.ok()
+}
+
+// Runs the search over a memory-mapped file, or any other buffer holding a
+// whole file. When possible the regex is run
+// over the whole buffer to find candidate lines, so that lines which can't
+// match or be shown as context are skipped without being looked at.
+fn process_mmap(data: &[u8], mut matcher: LineMatcher, sink: &mut dyn Sink) -> usize {
+    if matcher.multiline {
+        search_multiline(data, &mut matcher, sink);
+    } else {
+        let buffer_regex = matcher.pattern.buffer_regex();
+        match (std::str::from_utf8(data), buffer_regex) {
+            (Ok(text), Some(buffer_regex))
+                if !matcher.needs_every_line() && matcher.terminator == b'\n' =>
+            {
+                search_buffer(text, buffer_regex, &mut matcher, sink)
+            }
+            _ => feed_lines(data, &mut matcher, sink),
+        }
+    }
+
+    // A search that stopped early read up to the end of its last line
+    let bytes_searched = if matcher.is_done() {
+        let rest = &data[matcher.searched_to as usize..];
+        memchr(matcher.terminator, rest)
+            .map_or(data.len(), |end| matcher.searched_to as usize + end + 1)
+    } else {
+        data.len()
+    };
+    // Lines skipped over by `search_buffer` still count as searched
+    let lines_searched = if matcher.stats.is_some() {
+        count_lines(&data[..bytes_searched], matcher.terminator)
+    } else {
+        0
+    };
+    matcher.finish(sink, bytes_searched as u64, lines_searched)
+}
+
+// The number of lines in `data`, including a last one with no terminator
+fn count_lines(data: &[u8], terminator: u8) -> usize {
+    let ended = memchr::memchr_iter(terminator, data).count();
+    match data.last() {
+        Some(&last) if last != terminator => ended + 1,
+        _ => ended,
+    }
+}
+
+// Runs the regex over the whole buffer so that matches can span lines. Each
+// match is fed to the matcher as the lines it covers, numbered from the
+// first of them, with its context taken from before its first line and
+// after its last.
+fn search_multiline(data: &[u8], matcher: &mut LineMatcher, sink: &mut dyn Sink) {
+    if data.len() as u64 > MULTILINE_SIZE_LIMIT {
+        sink.emit(MatchEvent::ReadError(format!(
+            "larger than the {} byte limit for --multiline",
+            MULTILINE_SIZE_LIMIT
+        )));
+        return;
+    }
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(e) => {
+            sink.emit(MatchEvent::ReadError(e.to_string()));
+            return;
+        }
+    };
+
+    let mut index = 0;
+    let mut counted_to = 0;
+    let mut shown_to = 0;
+    // Where the lines already fed in end, so no line is shown twice
+    let mut fed_to = 0;
+    // The lines of the latest match, held back until any later matches on
+    // those same lines have been counted
+    let mut pending: Option<(usize, usize, usize, usize)> = None;
+
+    for found in matcher.pattern.regex.find_iter(text) {
+        // Past the final newline there are no more lines
+        if found.start == data.len() && data.ends_with(b"\n") {
+            break;
+        }
+        // On a line already printed along with an earlier match
+        if found.start < shown_to {
+            if let Some((_, _, _, matches)) = &mut pending {
+                *matches += 1;
+            }
+            continue;
+        }
+
+        let start = memrchr(b'\n', &data[..found.start]).map_or(0, |i| i + 1);
+        if let Some(block) = pending.take() {
+            fed_to = feed_multiline_match(text, block, start, matcher, sink);
+            if matcher.is_done() {
+                return;
+            }
+        }
+
+        // A match ending in a newline doesn't reach into the next line
+        let last = if found.is_empty() {
+            found.start
+        } else {
+            found.end - 1
+        };
+        let end = memchr(b'\n', &data[last..]).map_or(data.len(), |i| last + i);
+
+        index += memchr::memchr_iter(b'\n', &data[counted_to..start]).count();
+        counted_to = start;
+        shown_to = end + 1;
+
+        feed_before_context(text, fed_to, start, index, matcher, sink);
+        pending = Some((index, start, end, 1));
+    }
+
+    if let Some(block) = pending {
+        feed_multiline_match(text, block, text.len(), matcher, sink);
+    }
+}
+
+// Feeds in a match found by `search_multiline`, given as the index of its
+// first line, where its lines start and end, and how many matches they
+// hold, then its after-context up to `limit`. Returns where the lines fed in
+// end.
+fn feed_multiline_match(
+    text: &str,
+    (index, start, end, matches): (usize, usize, usize, usize),
+    limit: usize,
+    matcher: &mut LineMatcher,
+    sink: &mut dyn Sink,
+) -> usize {
+    let lines = &text[start..end];
+    matcher.feed_match(
+        index,
+        start as u64,
+        lines.strip_suffix('\r').unwrap_or(lines),
+        matches,
+        sink,
+    );
+
+    let next = index + memchr::memchr_iter(b'\n', lines.as_bytes()).count() + 1;
+    let mut pos = end + 1;
+    for index in next..next + matcher.after_context {
+        if pos >= limit {
+            break;
+        }
+        let line_end = memchr(b'\n', &text.as_bytes()[pos..limit]).map_or(limit, |i| pos + i);
+        matcher.feed_context(
+            index,
+            pos as u64,
+            strip_line_ending(&text[pos..line_end]),
+            sink,
+        );
+        pos = line_end + 1;
+    }
+    pos.min(text.len())
+}
+
+// Feeds in the before-context of a match found by `search_multiline`, whose
+// first line is numbered `index` and starts at `start`, leaving out any
+// lines before `fed_to`
+fn feed_before_context(
+    text: &str,
+    fed_to: usize,
+    start: usize,
+    index: usize,
+    matcher: &mut LineMatcher,
+    sink: &mut dyn Sink,
+) {
+    let mut first = start;
+    let mut count = 0;
+    while count < matcher.before_context && first > fed_to {
+        // `first - 1` is the newline ending the line before
+        first =
+            memrchr(b'\n', &text.as_bytes()[fed_to..first - 1]).map_or(fed_to, |i| fed_to + i + 1);
+        count += 1;
+    }
+
+    let mut offset = first;
+    for (i, line) in text[first..start].split_inclusive('\n').enumerate() {
+        matcher.feed_context(
+            index - count + i,
+            offset as u64,
+            strip_line_ending(line),
+            sink,
+        );
+        offset += line.len();
+    }
+}
+
+// Every line that has a match also contains a match of `buffer_regex`, so
+// only the lines holding those candidates need to be checked properly.
+// Lines in between are only fed to the matcher when they may be context.
+fn search_buffer(text: &str, buffer_regex: &Regex, matcher: &mut LineMatcher, sink: &mut dyn Sink) {
+    let mut pos = 0;
+    let mut index = 0;
+
+    while pos < text.len() {
+        let candidate = buffer_regex
+            .find_at(text, pos)
+            .map(|m| memrchr(b'\n', &text.as_bytes()[..m.start()]).map_or(0, |end| end + 1));
+
+        let skipped = &text[pos..candidate.unwrap_or(text.len())];
+        let skipped_lines = split_lines(skipped).count();
+        let after_context = matcher.after_countdown;
+        let before_context = matcher.before_context;
+        let mut line_start = pos;
+        for (n, line) in skipped.split_inclusive('\n').enumerate() {
+            if n < after_context || n + before_context >= skipped_lines {
+                matcher.feed(
+                    index + n,
+                    line_start as u64,
+                    strip_line_ending(line),
+                    None,
+                    sink,
+                );
+            }
+            line_start += line.len();
+        }
+        index += skipped_lines;
+
+        let Some(start) = candidate.filter(|&start| start < text.len()) else {
+            break;
+        };
+        let line = text[start..]
+            .split_inclusive('\n')
+            .next()
+            .unwrap_or_default();
+        matcher.feed(index, start as u64, strip_line_ending(line), None, sink);
+        if matcher.is_done() {
+            break;
+        }
+        index += 1;
+        pos = start + line.len();
+    }
+}
+
+// Feeds every line of a buffer to the matcher without allocating a `String`
+// per line. Unlike `search_buffer`, this splits lines at any terminator.
+fn feed_lines(data: &[u8], matcher: &mut LineMatcher, sink: &mut dyn Sink) {
+    let mut rest = data;
+
+    for index in 0.. {
+        if rest.is_empty() {
+            break;
+        }
+        let offset = (data.len() - rest.len()) as u64;
+        let line = match memchr(matcher.terminator, rest) {
+            Some(end) => {
+                let line = &rest[..end];
+                rest = &rest[end + 1..];
+                if matcher.terminator == b'\n' {
+                    line.strip_suffix(b"\r").unwrap_or(line)
+                } else {
+                    line
+                }
+            }
+            None => std::mem::take(&mut rest),
+        };
+
+        // A line that isn't UTF-8 is searched as lossy text
+        match std::str::from_utf8(line) {
+            Ok(line) => matcher.feed(index, offset, line, None, sink),
+            Err(_) => matcher.feed(
+                index,
+                offset,
+                &String::from_utf8_lossy(line),
+                Some(line),
+                sink,
+            ),
+        }
+        if matcher.is_done() {
+            break;
+        }
+    }
+}
+
+// Splits text into lines the same way `BufRead::lines` does
+fn split_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split_inclusive('\n').map(strip_line_ending)
+}
+
+// Runs the search over every line of `reader`, reporting what to show through
+// `sink` and returning how many lines were counted
+fn process_line<T: BufRead + Sized>(
+    mut reader: T,
+    mut matcher: LineMatcher,
+    sink: &mut dyn Sink,
+) -> Result<usize> {
+    if matcher.multiline {
+        let mut data = Vec::new();
+        reader
+            .take(MULTILINE_SIZE_LIMIT + 1)
+            .read_to_end(&mut data)?;
+        return Ok(process_mmap(&data, matcher, sink));
+    }
+
+    // Reused for every line so only lines that are kept or printed get allocated
+    let mut line = String::new();
+    // The bytes of the last line read, when it isn't valid UTF-8
+    let mut invalid = None;
+    let mut offset = 0;
+    let mut lines_searched = 0;
+
+    for index in 0.. {
+        line.clear();
+        let line_offset = offset;
+        match read_line_until(&mut reader, matcher.terminator, &mut line, &mut invalid) {
+            Ok(0) => break,
+            Ok(read) => {
+                offset += read as u64;
+                lines_searched += 1;
+                trim_line_terminator(&mut line, matcher.terminator);
+                if let Some(bytes) = &mut invalid {
+                    trim_bytes_terminator(bytes, matcher.terminator);
+                }
+            }
+            Err(e) => {
+                sink.emit(MatchEvent::ReadError(e.to_string()));
+                break;
+            }
+        }
+        matcher.feed(index, line_offset, &line, invalid.as_deref(), sink);
+        if matcher.is_done() {
+            break;
+        }
+    }
+
+    Ok(matcher.finish(sink, offset, lines_searched))
+}
+
+// Decides which lines to show as they are fed in one at a time, holding back
+// non-matching lines in case they turn out to be before-context
+struct LineMatcher<'a> {
+    pattern: &'a Pattern,
+    invert_match: bool,
+    // Matches are found by `search_multiline` and fed in with `feed_match`
+    multiline: bool,
+    count: bool,
+    count_matches: bool,
+    print_count: bool,
+    // Stop once this many lines have matched, and any after-context printed
+    max_count: Option<usize>,
+    files_with_matches: bool,
+    // Every line is shown, as context if it doesn't match
+    passthru: bool,
+    after_context: usize,
+    before_context: usize,
+    current_count: usize,
+    // Lines held back as possible before-context, with the bytes of any
+    // that aren't valid UTF-8
+    before_buffer: VecDeque<(usize, u64, String, Option<Vec<u8>>)>,
+    after_countdown: usize,
+    // Kept for a `Stats` event when asked for, except `bytes_searched`,
+    // which is filled in by `finish`
+    stats: Option<SearchStats>,
+    // Where the last line fed in ends, before its line ending
+    searched_to: u64,
+    // The byte lines end with, a newline unless `null_data` is set
+    terminator: u8,
+}
+
+impl<'a> LineMatcher<'a> {
+    fn new(pattern: &'a Pattern, config: &SearchConfig) -> Self {
+        // These only need to know how many lines matched, not what they were
+        let count = config.count || config.total || config.quiet || config.files_with_matches;
+        // Context is never shown when only counting, and with `passthru`
+        // every line is shown anyway
+        let (after_context, before_context) = if count || config.reports_every_line() {
+            (0, 0)
+        } else {
+            (config.after_context, config.before_context)
+        };
+
+        let print_count = (config.count || config.count_matches) && !config.quiet;
+        LineMatcher {
+            pattern,
+            invert_match: config.invert_match,
+            multiline: config.multiline,
+            count,
+            count_matches: config.count_matches,
+            print_count,
+            // Listing a file only takes one match, unless its count is wanted
+            max_count: if config.quiet || (config.files_with_matches && !print_count) {
+                Some(1)
+            } else {
+                config.max_count
+            },
+            files_with_matches: config.files_with_matches && !config.quiet,
+            passthru: config.reports_every_line() && !count && !config.multiline,
+            after_context,
+            before_context,
+            current_count: 0,
+            before_buffer: VecDeque::new(),
+            after_countdown: 0,
+            stats: config.stats.then(SearchStats::default),
+            searched_to: 0,
+            terminator: config.line_terminator(),
+        }
+    }
+
+    // Takes lines already known to hold `matches` matches of the pattern, as
+    // found by `search_multiline`
+    fn feed_match(
+        &mut self,
+        index: usize,
+        offset: u64,
+        lines: &str,
+        matches: usize,
+        sink: &mut dyn Sink,
+    ) {
+        self.searched_to = offset + lines.len() as u64;
+        if let Some(stats) = &mut self.stats {
+            stats.matched_lines += 1;
+            stats.matches += matches;
+        }
+        if self.count_matches {
+            self.current_count += matches;
+        } else {
+            self.current_count += 1;
+            emit_line(sink, self.count, true, index, offset, lines, None);
+        }
+    }
+
+    // Takes a line known to be context for a match found by
+    // `search_multiline`
+    fn feed_context(&mut self, index: usize, offset: u64, line: &str, sink: &mut dyn Sink) {
+        self.searched_to = offset + line.len() as u64;
+        emit_line(sink, self.count, false, index, offset, line, None);
+    }
+
+    // Takes the next line, which starts `offset` bytes into the input. A
+    // line that isn't valid UTF-8 comes as lossy text, with its `bytes`.
+    fn feed(
+        &mut self,
+        index: usize,
+        offset: u64,
+        line: &str,
+        bytes: Option<&[u8]>,
+        sink: &mut dyn Sink,
+    ) {
+        self.searched_to = offset + line.len() as u64;
+        if self.count_matches {
+            let matches = self.pattern.regex.find_iter(line).count();
+            self.current_count += matches;
+            if let Some(stats) = self.stats.as_mut().filter(|_| matches > 0) {
+                stats.matched_lines += 1;
+                stats.matches += matches;
+            }
+            return;
+        }
+
+        // Past the last match, lines can only be after-context
+        let match_found =
+            !self.reached_max_count() && self.pattern.is_match(line) != self.invert_match;
+
+        if match_found {
+            // Only real matches count, never the context printed around them
+            self.current_count += 1;
+            if let Some(stats) = &mut self.stats {
+                stats.matched_lines += 1;
+                stats.matches += if self.invert_match {
+                    1
+                } else {
+                    self.pattern.regex.find_iter(line).count()
+                };
+            }
+
+            for (before_index, before_offset, before_line, before_bytes) in
+                self.before_buffer.drain(..)
+            {
+                emit_line(
+                    sink,
+                    self.count,
+                    false,
+                    before_index,
+                    before_offset,
+                    &before_line,
+                    before_bytes.as_deref(),
+                );
+            }
+
+            emit_line(sink, self.count, true, index, offset, line, bytes);
+            self.after_countdown = self.after_context;
+        } else if self.after_countdown > 0 {
+            emit_line(sink, self.count, false, index, offset, line, bytes);
+            self.after_countdown -= 1;
+        } else if self.passthru {
+            emit_line(sink, self.count, false, index, offset, line, bytes);
+        } else if self.before_context > 0 {
+            // Once the buffer is full, recycle the oldest line's allocation
+            let mut owned = if self.before_buffer.len() == self.before_context {
+                self.before_buffer
+                    .pop_front()
+                    .map(|(_, _, line, _)| line)
+                    .unwrap_or_default()
+            } else {
+                String::new()
+            };
+            owned.clear();
+            owned.push_str(line);
+            self.before_buffer
+                .push_back((index, offset, owned, bytes.map(<[u8]>::to_vec)));
+        }
+    }
+
+    // Whether every line has to be looked at, rather than only the ones
+    // containing a match
+    fn needs_every_line(&self) -> bool {
+        self.invert_match || self.count_matches || self.passthru
+    }
+
+    // Whether only the number of matching lines matters, so lines never
+    // have to be looked at one by one
+    fn is_summary_only(&self) -> bool {
+        self.count
+    }
+
+    fn reached_max_count(&self) -> bool {
+        self.max_count.is_some_and(|max| self.current_count >= max)
+    }
+
+    // Whether the rest of the input can be skipped
+    fn is_done(&self) -> bool {
+        self.reached_max_count() && self.after_countdown == 0 && !self.passthru
+    }
+
+    // Reports what was found, after `lines_searched` lines making up
+    // `bytes_searched` bytes of the input
+    fn finish(self, sink: &mut dyn Sink, bytes_searched: u64, lines_searched: usize) -> usize {
+        if self.print_count {
+            // With `files_with_matches`, files without a match aren't listed
+            if self.current_count > 0 || !self.files_with_matches {
+                sink.emit(MatchEvent::Count(self.current_count));
+            }
+        } else if self.files_with_matches && self.current_count > 0 {
+            sink.emit(MatchEvent::FileMatched);
+        }
+        if let Some(stats) = self.stats {
+            sink.emit(MatchEvent::Stats(SearchStats {
+                lines_searched,
+                bytes_searched,
+                ..stats
+            }));
+        }
+        self.current_count
+    }
+}
+
+// Strips the line terminator the same way `BufRead::lines` does
+fn strip_line_ending(line: &str) -> &str {
+    match line.strip_suffix('\n') {
+        Some(line) => line.strip_suffix('\r').unwrap_or(line),
+        None => line,
+    }
+}
+
+fn trim_line_ending(line: &mut String) {
+    let len = strip_line_ending(line).len();
+    line.truncate(len);
+}
+
+// Strips the line ending from a line ended by `terminator`, which for a
+// newline may come after a carriage return
+fn trim_line_terminator(line: &mut String, terminator: u8) {
+    if terminator == b'\n' {
+        trim_line_ending(line);
+    } else if line.as_bytes().last() == Some(&terminator) {
+        line.pop();
+    }
+}
+
+// Reads up to and including the next `terminator` into `line`, the same way
+// `BufRead::read_line` reads up to the next newline. A line that isn't valid
+// UTF-8 is read as lossy text, with its bytes put in `invalid`.
+fn read_line_until<R: BufRead>(
+    reader: &mut R,
+    terminator: u8,
+    line: &mut String,
+    invalid: &mut Option<Vec<u8>>,
+) -> Result<usize> {
+    let mut bytes = std::mem::take(line).into_bytes();
+    let read = reader.read_until(terminator, &mut bytes)?;
+    *invalid = decode_line(bytes, line);
+    Ok(read)
+}
+
+// Puts the text of a line read as bytes in `line`, with each invalid UTF-8
+// sequence replaced by U+FFFD. The bytes are given back if there were any.
+fn decode_line(bytes: Vec<u8>, line: &mut String) -> Option<Vec<u8>> {
+    match String::from_utf8(bytes) {
+        Ok(text) => {
+            *line = text;
+            None
+        }
+        Err(e) => {
+            let bytes = e.into_bytes();
+            *line = String::from_utf8_lossy(&bytes).into_owned();
+            Some(bytes)
+        }
+    }
+}
+
+// Strips the line ending from a line's bytes, as `trim_line_terminator`
+// does from its text
+fn trim_bytes_terminator(bytes: &mut Vec<u8>, terminator: u8) {
+    if bytes.last() == Some(&terminator) {
+        bytes.pop();
+        if terminator == b'\n' && bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+    }
+}
+
+// Passes a matching line or a line of context on to be printed, unless only
+// counting
+fn emit_line(
+    sink: &mut dyn Sink,
+    count: bool,
+    is_match: bool,
+    index: usize,
+    offset: u64,
+    line: &str,
+    bytes: Option<&[u8]>,
+) {
+    if count {
+        return;
+    }
+    if let Some(bytes) = bytes {
+        sink.emit(MatchEvent::LineBytes(bytes.to_vec()));
+    }
+    let line = line.to_string();
+    sink.emit(if is_match {
+        MatchEvent::Line {
+            index,
+            offset,
+            line,
+        }
+    } else {
+        MatchEvent::Context {
+            index,
+            offset,
+            line,
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Searches `input` with `config`, returning what was emitted and counted
+    fn search(config: SearchConfig, input: &str) -> (Vec<MatchEvent>, usize) {
+        let search = CompiledSearch::new(config).unwrap();
+        let mut sink = VecSink::default();
+        let count = search_reader_with_sink(input.as_bytes(), &search, &mut sink).unwrap();
+        (sink.events, count)
+    }
+
+    fn line(index: usize, offset: u64, line: &str) -> MatchEvent {
+        MatchEvent::Line {
+            index,
+            offset,
+            line: line.to_string(),
+        }
+    }
+
+    fn context(index: usize, offset: u64, line: &str) -> MatchEvent {
+        MatchEvent::Context {
+            index,
+            offset,
+            line: line.to_string(),
+        }
+    }
+
+    #[test]
+    fn matching_lines_are_emitted_with_their_offsets() {
+        let config = SearchConfig {
+            pattern: "b".to_string(),
+            ..SearchConfig::default()
+        };
+
+        let (events, count) = search(config, "abc\nxyz\nb\n");
+
+        assert_eq!(events, [line(0, 0, "abc"), line(2, 8, "b")]);
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn context_lines_surround_a_match() {
+        let config = SearchConfig {
+            pattern: "c".to_string(),
+            before_context: 1,
+            after_context: 1,
+            ..SearchConfig::default()
+        };
+
+        let (events, _) = search(config, "a\nb\nc\nd\ne\n");
+
+        assert_eq!(
+            events,
+            [context(1, 2, "b"), line(2, 4, "c"), context(3, 6, "d")]
+        );
+    }
+
+    #[test]
+    fn count_emits_only_the_count() {
+        let config = SearchConfig {
+            pattern: "a".to_string(),
+            count: true,
+            ..SearchConfig::default()
+        };
+
+        let (events, count) = search(config, "a\nb\naa\n");
+
+        assert_eq!(events, [MatchEvent::Count(2)]);
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn count_matches_counts_each_match_on_a_line() {
+        let config = SearchConfig {
+            pattern: "ab".to_string(),
+            count_matches: true,
+            ..SearchConfig::default()
+        };
+
+        let (events, count) = search(config, "ab ab xab\nnone\n");
+
+        assert_eq!(events, [MatchEvent::Count(3)]);
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn inverted_count_is_the_same_with_context() {
+        let input = "a\nneedle\nb\nc\nneedle\nd\n";
+        for (before_context, after_context) in [(0, 0), (1, 1), (0, 2), (3, 0)] {
+            let config = SearchConfig {
+                pattern: "needle".to_string(),
+                count: true,
+                invert_match: true,
+                before_context,
+                after_context,
+                ..SearchConfig::default()
+            };
+
+            assert_eq!(search(config, input), (vec![MatchEvent::Count(4)], 4));
+        }
+    }
+
+    #[test]
+    fn invert_match_emits_the_other_lines() {
+        let config = SearchConfig {
+            pattern: "a".to_string(),
+            invert_match: true,
+            ..SearchConfig::default()
+        };
+
+        let (events, _) = search(config, "a\nb\naa\nc\n");
+
+        assert_eq!(events, [line(1, 2, "b"), line(3, 7, "c")]);
+    }
+
+    // Writes `contents` to a file of its own under the system's temporary
+    // directory, returning its path
+    fn temp_file(name: &str, contents: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("grep-lite-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    // Inputs with matches at the start and end, blank lines, CRLF endings
+    // and no final newline, for comparing ways of searching
+    const FIXTURES: [&str; 5] = [
+        "needle first\nhay\n\nhay needle\nhay\nhay\nhay\nlast needle",
+        "hay\r\nneedle\r\nhay\r\n\r\nneedle needle\r\n",
+        "needle\nneedle\nneedle\n",
+        "hay\nhay\n",
+        "",
+    ];
+
+    // Settings that take the searches down their different paths
+    fn configs() -> Vec<SearchConfig> {
+        let config = || SearchConfig {
+            pattern: "needle".to_string(),
+            ..SearchConfig::default()
+        };
+        vec![
+            config(),
+            SearchConfig {
+                pattern: "^needle|needle$".to_string(),
+                ..config()
+            },
+            SearchConfig {
+                before_context: 1,
+                after_context: 2,
+                ..config()
+            },
+            SearchConfig {
+                invert_match: true,
+                before_context: 1,
+                ..config()
+            },
+            SearchConfig {
+                max_count: Some(1),
+                after_context: 1,
+                ..config()
+            },
+            SearchConfig {
+                count: true,
+                ..config()
+            },
+            SearchConfig {
+                count_matches: true,
+                ..config()
+            },
+            SearchConfig {
+                stats: true,
+                ..config()
+            },
+            SearchConfig {
+                passthru: true,
+                ..config()
+            },
+        ]
+    }
+
+    #[test]
+    fn mmap_and_streaming_searches_agree() {
+        for (n, fixture) in FIXTURES.iter().enumerate() {
+            let path = temp_file(&format!("mmap-and-streaming-{}", n), fixture.as_bytes());
+            for config in configs() {
+                let streamed = search(config.clone(), fixture);
+                let with_file = |mmap: bool| {
+                    let search = CompiledSearch::new(SearchConfig {
+                        mmap,
+                        no_mmap: !mmap,
+                        ..config.clone()
+                    })
+                    .unwrap();
+                    let mut sink = VecSink::default();
+                    let count = search_file_with_sink(&path, &search, &mut sink).unwrap();
+                    (sink.events, count)
+                };
+
+                assert_eq!(with_file(true), streamed, "{:?} {:?}", fixture, config);
+                assert_eq!(with_file(false), streamed, "{:?} {:?}", fixture, config);
+            }
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn only_unmatched_reports_matching_lines_as_context() {
+        let config = |invert_match, only_unmatched| SearchConfig {
+            pattern: r"\d".to_string(),
+            invert_match,
+            only_unmatched,
+            after_context: 2,
+            ..SearchConfig::default()
+        };
+        let input = "a1b\nzzz\n1\nyy\n";
+
+        assert_eq!(
+            search(config(true, true), input),
+            (
+                vec![
+                    context(0, 0, "a1b"),
+                    line(1, 4, "zzz"),
+                    context(2, 8, "1"),
+                    line(3, 10, "yy"),
+                ],
+                2
+            )
+        );
+        // Without `invert_match` it changes nothing, and it isn't `passthru`
+        assert_eq!(
+            search(config(false, true), input),
+            search(config(false, false), input)
+        );
+        assert_eq!(
+            search(config(true, false), input),
+            (
+                vec![line(1, 4, "zzz"), context(2, 8, "1"), line(3, 10, "yy")],
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn lines_that_are_not_utf8_are_searched_as_lossy_text() {
+        let input = b"foo ok\nfoo \xff bad\r\nfoo after\n";
+        let bytes = MatchEvent::LineBytes(b"foo \xff bad".to_vec());
+        let config = |pattern: &str| SearchConfig {
+            pattern: pattern.to_string(),
+            before_context: 1,
+            ..SearchConfig::default()
+        };
+        let path = temp_file("not-utf8", input);
+
+        for pattern in ["foo", "after", "\u{FFFD}"] {
+            let search = CompiledSearch::new(config(pattern)).unwrap();
+            let mut streamed = VecSink::default();
+            search_reader_with_sink(&input[..], &search, &mut streamed).unwrap();
+            let mut mapped = VecSink::default();
+            let search = CompiledSearch::new(SearchConfig {
+                mmap: true,
+                ..config(pattern)
+            })
+            .unwrap();
+            search_file_with_sink(&path, &search, &mut mapped).unwrap();
+
+            assert_eq!(mapped.events, streamed.events, "{}", pattern);
+        }
+
+        let search = CompiledSearch::new(config("after")).unwrap();
+        let mut sink = VecSink::default();
+        assert_eq!(
+            search_reader_with_sink(&input[..], &search, &mut sink).unwrap(),
+            1
+        );
+        // Held back as before-context, the line still comes with its bytes
+        assert_eq!(
+            sink.events,
+            [
+                bytes.clone(),
+                context(1, 7, "foo \u{FFFD} bad"),
+                line(2, 18, "foo after"),
+            ]
+        );
+        let found =
+            search_reader(&input[..], &CompiledSearch::new(config("bad")).unwrap()).unwrap();
+        assert_eq!(found[0].line, "foo \u{FFFD} bad");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn files_at_the_mmap_threshold_are_mapped() {
+        let path = temp_file("mmap-threshold", b"hay\nneedle\n");
+        let file = File::open(&path).unwrap();
+        let metadata = file.metadata().unwrap();
+        let mapped = |mmap_threshold| {
+            let config = SearchConfig {
+                mmap_threshold,
+                ..SearchConfig::default()
+            };
+            map_file(&file, &metadata, &config).is_some()
+        };
+
+        assert!(!mapped(MMAP_THRESHOLD));
+        assert!(mapped(4));
+        assert!(mapped(11));
+        assert!(!mapped(12));
+
+        // The whole search goes the same way, finding the same lines
+        let search = CompiledSearch::new(SearchConfig {
+            pattern: "needle".to_string(),
+            mmap_threshold: 4,
+            ..SearchConfig::default()
+        })
+        .unwrap();
+        let mut sink = VecSink::default();
+        assert_eq!(search_file_with_sink(&path, &search, &mut sink).unwrap(), 1);
+        assert_eq!(sink.events, [line(1, 4, "needle")]);
+        fs::remove_file(path).unwrap();
+    }
+
+    // A pseudo-random input of `lines` lines mixing words that do and don't
+    // match in different cases, with some CRLF endings and sometimes no
+    // final newline. The same `seed` always gives the same input.
+    fn random_input(seed: u64, lines: usize) -> String {
+        const WORDS: [&str; 6] = ["hay", "needle", "Needle", "NEEDLE", "stack", ""];
+        let mut state = seed;
+        let mut next = |n: usize| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as usize % n
+        };
+        let mut input = String::new();
+        for _ in 0..lines {
+            let words: Vec<&str> = (0..next(4)).map(|_| WORDS[next(WORDS.len())]).collect();
+            input += &words.join(" ");
+            input += if next(5) == 0 { "\r\n" } else { "\n" };
+        }
+        if next(2) == 0 {
+            input.pop();
+        }
+        input
+    }
+
+    #[test]
+    fn counting_fast_path_agrees_with_the_line_by_line_search() {
+        for seed in 0..20 {
+            let input = random_input(seed, 200);
+            let path = temp_file(&format!("counting-fast-path-{}", seed), input.as_bytes());
+            for (invert_match, ignore_case, max_count) in [
+                (false, false, None),
+                (true, false, None),
+                (false, true, None),
+                (true, true, None),
+                (false, true, Some(3)),
+                (true, false, Some(3)),
+            ] {
+                let config = SearchConfig {
+                    pattern: "needle".to_string(),
+                    count: true,
+                    invert_match,
+                    ignore_case,
+                    max_count,
+                    ..SearchConfig::default()
+                };
+                // A small file is read whole and counted at once, where
+                // standard input is searched a line at a time
+                let compiled = CompiledSearch::new(config.clone()).unwrap();
+                let mut sink = VecSink::default();
+                let count = search_file_with_sink(&path, &compiled, &mut sink).unwrap();
+
+                assert_eq!(
+                    (sink.events, count),
+                    search(config.clone(), &input),
+                    "{:?}",
+                    config
+                );
+            }
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    fn replaced(contents: &str, pattern: &str, replacement: &str, name: &str) -> String {
+        let path = temp_file(name, contents.as_bytes());
+        let search = SearchConfig::new(pattern).build().unwrap();
+        replace_in_file(&path, &search, replacement, None).unwrap();
+        let replaced = fs::read_to_string(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        replaced
+    }
+
+    #[test]
+    fn replace_in_file_keeps_crlf_line_endings() {
+        let replaced = replaced("cat\r\ndog\r\ncat\r\n", "cat", "cow", "replace-crlf");
+
+        assert_eq!(replaced, "cow\r\ndog\r\ncow\r\n");
+    }
+
+    #[test]
+    fn replace_in_file_keeps_a_missing_final_newline() {
+        let replaced = replaced("cat\ndog\ncat", "cat", "cow", "replace-no-final-newline");
+
+        assert_eq!(replaced, "cow\ndog\ncow");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replace_in_file_edits_the_target_of_a_symlink() {
+        let target = temp_file("replace-symlink-target", b"cat\ndog\n");
+        let link = format!("{}-link", target);
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let search = SearchConfig::new("cat").build().unwrap();
+
+        assert_eq!(replace_in_file(&link, &search, "cow", None).unwrap(), 1);
+
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new(&target));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "cow\ndog\n");
+        fs::remove_file(link).unwrap();
+        fs::remove_file(target).unwrap();
+    }
+
+    #[test]
+    fn smart_case_ignores_case_only_for_lowercase_patterns() {
+        let smart_case = SearchConfig {
+            smart_case: true,
+            ..SearchConfig::default()
+        };
+
+        assert!(!is_case_insensitive(&smart_case, "Error"));
+        assert!(is_case_insensitive(&smart_case, "error"));
+        assert!(!is_case_insensitive(&smart_case, r"\bWARN\b"));
+        assert!(is_case_insensitive(&smart_case, r"\bwarn\b"));
+        // Escapes aren't letters written out, whatever their case
+        assert!(is_case_insensitive(&smart_case, r"\S+error\x41"));
+        assert!(!is_case_insensitive(&smart_case, "[A-Z]rror"));
+    }
+
+    #[test]
+    fn ignore_case_and_case_sensitive_override_smart_case() {
+        let config = |ignore_case, case_sensitive, smart_case| SearchConfig {
+            ignore_case,
+            case_sensitive,
+            smart_case,
+            ..SearchConfig::default()
+        };
+
+        assert!(!is_case_insensitive(&config(false, false, false), "error"));
+        assert!(is_case_insensitive(&config(true, false, false), "Error"));
+        assert!(is_case_insensitive(&config(true, true, true), "Error"));
+        assert!(!is_case_insensitive(&config(false, true, true), "error"));
+    }
+
+    #[test]
+    fn smart_case_searches_match_accordingly() {
+        let found = |pattern: &str| {
+            let config = SearchConfig {
+                pattern: pattern.to_string(),
+                smart_case: true,
+                ..SearchConfig::default()
+            };
+            search(config, "ERROR\nError\nerror\nWARN\nwarn\n").1
+        };
+
+        assert_eq!(found("error"), 3);
+        assert_eq!(found("Error"), 1);
+        assert_eq!(found(r"\bWARN\b"), 1);
+    }
+
+    #[test]
+    fn ignore_case_without_unicode_only_folds_ascii_letters() {
+        let found = |pattern: &str, unicode: bool, input: &str| {
+            let config = SearchConfig {
+                pattern: pattern.to_string(),
+                ignore_case: true,
+                unicode,
+                ..SearchConfig::default()
+            };
+            search(config, input).1
+        };
+        // The Kelvin sign and the long s fold to `k` and `s` in Unicode
+        let input = "k\nK\n\u{212A}\ns\nS\n\u{17F}\n";
+
+        assert_eq!(found("k", true, input), 3);
+        assert_eq!(found("k", false, input), 2);
+        assert_eq!(found("S", true, input), 3);
+        assert_eq!(found("S", false, input), 2);
+        assert_eq!(found("\u{212A}", false, input), 1);
+        // Letters outside ASCII then only match themselves
+        assert_eq!(
+            found("\u{e9}t\u{e9}", true, "\u{e9}t\u{e9}\n\u{c9}T\u{c9}\n"),
+            2
+        );
+        assert_eq!(
+            found("\u{e9}t\u{e9}", false, "\u{e9}t\u{e9}\n\u{c9}T\u{c9}\n"),
+            1
+        );
+    }
+
+    #[test]
+    fn lazy_multiline_match_spans_the_lines_it_covers() {
+        let config = SearchConfig {
+            pattern: "BEGIN.*?END".to_string(),
+            multiline: true,
+            multiline_dotall: true,
+            ..SearchConfig::default()
+        };
+
+        let (events, count) = search(
+            config,
+            "zero\npre BEGIN one\ntwo\nthree END post END\nfour\n",
+        );
+
+        assert_eq!(
+            events,
+            [line(1, 5, "pre BEGIN one\ntwo\nthree END post END")]
+        );
+        assert_eq!(count, 1);
+    }
+
+    fn pattern(pattern: &str) -> Pattern {
+        Pattern::new(&SearchConfig {
+            pattern: pattern.to_string(),
+            ..SearchConfig::default()
+        })
+        .unwrap()
+    }
+
+    // Lines like those from the `generate_lines` example, one in a thousand
+    // holding the needle
+    fn haystack(lines: usize) -> Vec<String> {
+        (0..lines)
+            .map(|i| {
+                if i % 1000 == 0 {
+                    format!("{} found the needle in the haystack", i)
+                } else {
+                    format!(
+                        "{} lorem ipsum dolor sit amet, consectetur adipiscing elit",
+                        i
+                    )
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn required_literal_is_found_where_every_match_needs_it() {
+        let literal = |pattern: &str| {
+            self::pattern(pattern)
+                .literal
+                .map(|literal| String::from_utf8(literal.needle().to_vec()).unwrap())
+        };
+
+        assert_eq!(literal("needle").as_deref(), Some("needle"));
+        assert_eq!(
+            literal(r"\d+ found the needle").as_deref(),
+            Some(" found the needle")
+        );
+        assert_eq!(literal("(?:needle)+x?").as_deref(), Some("needle"));
+        assert_eq!(literal("needle|haystack"), None);
+        assert_eq!(literal("x*"), None);
+    }
+
+    #[test]
+    fn literal_prefilter_agrees_with_the_regex() {
+        for pattern in ["needle", r"\d+ found", "(?i)NEEDLE", "ipsum.*elit$", "^1"] {
+            let pattern = self::pattern(pattern);
+            for line in haystack(3000) {
+                assert_eq!(
+                    pattern.is_match(&line),
+                    pattern.regex.is_match(&line),
+                    "{}",
+                    line
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn output_is_the_same_without_the_literal_prefilter() {
+        for seed in 0..10 {
+            let input = random_input(seed, 100);
+            for pattern in ["needle", "(?i)needle", r"\bneedle stack", "ne+dle$"] {
+                for config in configs() {
+                    let config = SearchConfig {
+                        pattern: pattern.to_string(),
+                        ..config
+                    };
+                    let with_literal = CompiledSearch::new(config.clone()).unwrap();
+                    let mut without_literal = CompiledSearch::new(config).unwrap();
+                    without_literal.pattern.literal = None;
+
+                    let mut with = VecSink::default();
+                    let mut without = VecSink::default();
+                    search_reader_with_sink(input.as_bytes(), &with_literal, &mut with).unwrap();
+                    search_reader_with_sink(input.as_bytes(), &without_literal, &mut without)
+                        .unwrap();
+
+                    assert_eq!(with.events, without.events, "{:?}", pattern);
+                }
+            }
+        }
+    }
+
+    // Compares the literal prefilter with always running the regex. Run it
+    // with `cargo test --release --lib -- --ignored --nocapture prefilter`.
+    #[test]
+    #[ignore]
+    fn benchmark_literal_prefilter() {
+        let lines = haystack(1_000_000);
+        let pattern = pattern(r"\d+ found the needle");
+        let time = |is_match: &dyn Fn(&str) -> bool| {
+            let started = std::time::Instant::now();
+            let found = lines.iter().filter(|line| is_match(line)).count();
+            (found, started.elapsed())
+        };
+
+        let (prefiltered, with_literal) = time(&|line| pattern.is_match(line));
+        let (unfiltered, regex_only) = time(&|line| pattern.regex.is_match(line));
+
+        assert_eq!(prefiltered, unfiltered);
+        eprintln!("literal prefilter: {:?}", with_literal);
+        eprintln!("regex only:        {:?}", regex_only);
+    }
+
+    #[test]
+    fn files_with_matches_stops_at_the_first_match() {
+        let config = SearchConfig {
+            pattern: "a".to_string(),
+            files_with_matches: true,
+            ..SearchConfig::default()
+        };
+
+        let (events, _) = search(config, "a\nb\na\n");
+
+        assert_eq!(events, [MatchEvent::FileMatched]);
+    }
+}
@@ -1,248 +1,2997 @@
 use clap::Parser;
-use colored::*;
-use regex::{Regex, RegexBuilder};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use grep_lite::{
+    count_replaced_lines, json, replace_in_file, replaced_file_contents, search_file_with_sink,
+    search_reader_with_sink, CompiledRegex, CompiledSearch, GrepError, MatchEvent, SearchConfig,
+    SearchStats, Sink, VecSink, MMAP_THRESHOLD,
+};
+use rayon::prelude::*;
+use similar::{ChangeTag, TextDiff};
 use std::{
-    collections::VecDeque,
-    fs::File,
-    io::{self, BufRead, BufReader, Result},
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    ffi::OsString,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, IsTerminal, Result, Write},
+    ops::Range,
+    panic,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+    },
+    thread,
+    time::{Duration, Instant},
 };
 use walkdir::WalkDir;
 
 #[derive(Parser)]
 #[clap(
     name = "grep-lite",
-    version = "0.1",
-    about = "searches for patterns in files"
+    version = "0.2",
+    about = "searches for patterns in files",
+    // `-h` is taken by `--no-filename`, as in grep
+    disable_help_flag = true,
+    // Options from `GREP_LITE_OPTIONS` come first, so giving one again on
+    // the command line replaces it rather than being an error
+    args_override_self = true
 )]
 struct Grep {
-    pattern: String,
+    #[arg(required_unless_present_any = ["type_list", "regexp"])]
+    pattern: Option<String>,
 
     #[arg(required = false)]
     inputs: Vec<String>,
 
+    /// Search for this pattern. May be given more than once to match lines
+    /// containing any of them, each highlighted in its own colour. Every
+    /// argument after the options is then an input.
+    #[arg(short = 'e', long, value_name = "PATTERN")]
+    regexp: Vec<String>,
+
+    /// Match case-insensitively. Takes precedence over `--case-sensitive`
+    /// and `--smart-case`.
     #[arg(required = false, short, long)]
     ignore_case: bool,
 
+    /// Match case-sensitively, even with `--smart-case`
+    #[arg(long)]
+    case_sensitive: bool,
+
+    /// Match case-insensitively unless the pattern contains an uppercase
+    /// letter. Escapes such as `\W` or `\p{Lu}` don't count.
+    #[arg(short = 'S', long)]
+    smart_case: bool,
+
     #[arg(short = 'v', long)]
     invert_match: bool,
 
+    /// Search for the pattern as plain text rather than a regex
+    #[arg(short = 'F', long)]
+    fixed_strings: bool,
+
+    /// Stop searching a file after this many matching lines, once the
+    /// context after the last of them has been printed
+    #[arg(short = 'm', long, value_name = "NUM")]
+    max_count: Option<usize>,
+
+    /// Print how many lines matched in each file, including files with
+    /// none. With `-l`, only files with at least one match are listed.
     #[arg(short, long)]
     count: bool,
 
+    /// Print nothing, and exit with status 0 as soon as a match is found
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Only print the names of files containing a match. With `-c`, their
+    /// counts are printed after them.
+    #[arg(short = 'l', long)]
+    files_with_matches: bool,
+
+    /// Count every occurrence of the pattern rather than matching lines
+    #[arg(long, conflicts_with = "invert_match")]
+    count_matches: bool,
+
+    /// Print the count summed over every input at the end. Per-file counts
+    /// are only printed as well when `-c` is given.
+    #[arg(long)]
+    total: bool,
+
+    /// Print one line at the end saying how many lines matched and in how
+    /// many files, such as `matched 42 lines across 7 files`. With
+    /// `--count-matches` it gives the number of matches instead.
+    #[arg(long)]
+    summary: bool,
+
+    /// Print how many files, lines and bytes were searched and matched, and
+    /// how long it took, to stderr after the results. With `--json` they go
+    /// in the summary message instead.
+    #[arg(long)]
+    stats: bool,
+
+    /// Search directories and everything in them, or the current directory
+    /// when no inputs are given. Each directory's entries are searched in
+    /// order of file name, unless `--sort none` is given. A file reached
+    /// more than once, through overlapping directories or `-L`, is only
+    /// searched once.
     #[arg(short, long)]
     recursive: bool,
 
-    #[arg(short = 'A', long = "after", default_value = "0")]
-    after_context: usize,
+    /// What to do with directories given as inputs: report them as
+    /// unreadable (`read`), leave them out (`skip`), or search everything in
+    /// them as `-r` does (`recurse`)
+    #[arg(short = 'd', long, value_enum, value_name = "ACTION", default_value_t = DirectoryAction::Read)]
+    directories: DirectoryAction,
 
-    #[arg(short = 'B', long = "before", default_value = "0")]
-    before_context: usize,
+    /// Search at most this many levels below each input when recursing, so
+    /// 1 searches just the files directly inside it. 0 searches only the
+    /// inputs themselves. Needs `-r` or `-d recurse`.
+    #[arg(long, value_name = "NUM")]
+    max_depth: Option<usize>,
 
-    #[arg(short = 'C', default_value = "0")]
-    context: usize,
-}
+    /// Follow symbolic links when recursing. A link that loops back to a
+    /// directory above it is reported and skipped. Links named as inputs
+    /// are always followed.
+    #[arg(short = 'L', long)]
+    follow: bool,
 
-fn main() -> Result<()> {
-    let args = Grep::parse();
-    let re = RegexBuilder::new(&args.pattern)
-        .case_insensitive(args.ignore_case)
-        .build()
-        .unwrap();
+    /// Print the file name for each match. This is the default when more
+    /// than one file is searched.
+    #[arg(short = 'H', long, overrides_with = "no_filename")]
+    with_filename: bool,
 
-    let inputs = &args.inputs;
-    let is_multiple_files = inputs.len() > 1;
+    /// Never print file names, even when searching more than one file
+    #[arg(short = 'h', long, overrides_with = "with_filename")]
+    no_filename: bool,
 
-    let before_context = if args.context > 0 {
-        args.context
-    } else {
-        args.before_context
-    };
+    /// Print each file name once above its matches, with a blank line
+    /// between files. This is the default when printing file names to a
+    /// terminal.
+    #[arg(long, overrides_with = "no_heading")]
+    heading: bool,
 
-    let after_context = if args.context > 0 {
-        args.context
-    } else {
-        args.after_context
-    };
+    /// Print the file name on every line instead of in a heading
+    #[arg(long, overrides_with = "heading")]
+    no_heading: bool,
 
-    if inputs.is_empty() {
-        let stdin = io::stdin();
-        let reader = stdin.lock();
-        process_line(
-            reader,
-            &re,
-            args.invert_match,
-            args.count,
-            false,
-            "-",
-            after_context,
-            before_context,
-        )?;
+    /// When to colour matches, file names and the like. `auto` colours them
+    /// only when printing to a terminal and `NO_COLOR` isn't set.
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorMode::Auto)]
+    color: ColorMode,
+
+    /// Write the results to this file instead of stdout, replacing anything
+    /// in it. They're only coloured with `--color=always`.
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Print matching lines with each match replaced by this text, which may
+    /// refer to capture groups as `$1` or `${name}`. Write `$$` for a literal
+    /// `$`, and `${1}x` when a group is followed by a letter or digit. A
+    /// group that doesn't exist or didn't take part in the match is replaced
+    /// by nothing.
+    #[arg(long, value_name = "REPLACEMENT")]
+    replace: Option<String>,
+
+    /// Edit each file, replacing matches with the `--replace` text, instead
+    /// of printing them. Files are rewritten atomically, and only regular
+    /// files are edited; a symlink stays a symlink, with the file it points
+    /// to edited. Each file is held in memory along with its edited
+    /// copy, with a warning for files over 256 MiB.
+    #[arg(long, requires = "replace", conflicts_with_all = ["invert_match", "multiline"])]
+    in_place: bool,
+
+    /// With `--in-place`, keep a copy of each edited file's original contents
+    /// under its name plus this suffix, such as `.bak`. An existing copy is
+    /// overwritten, and a file whose copy can't be made is left unedited.
+    #[arg(
+        long,
+        visible_alias = "backup-suffix",
+        value_name = "SUFFIX",
+        requires = "in_place"
+    )]
+    backup: Option<String>,
+
+    /// With `--in-place`, leave every file as it is and say on stderr how
+    /// many lines of each would change
+    #[arg(long, requires = "in_place")]
+    dry_run: bool,
+
+    /// Print a unified diff of what `--replace` would change in each file,
+    /// instead of the matching lines. Nothing is written, even with
+    /// `--in-place`, so `--diff --in-place` is a dry run.
+    #[arg(long, requires = "replace")]
+    diff: bool,
+
+    /// Print the 1-based line number of each line, counting from the start
+    /// of its file
+    #[arg(short = 'n', long)]
+    line_number: bool,
+
+    /// Print the 1-based column of the first match, after the line number
+    /// with `-n`, counted in bytes. Lines with no match to point at, such as those
+    /// printed by `-v` or as context, get column 1.
+    #[arg(long)]
+    column: bool,
+
+    /// With `--column`, count columns in characters rather than bytes
+    #[arg(long, requires = "column")]
+    column_chars: bool,
+
+    /// Print the 0-based byte offset of each line within its file, after the
+    /// line number and column when they are printed
+    #[arg(short = 'b', long)]
+    byte_offset: bool,
+
+    /// Print `[Omitted long line with N matches]` in place of any line, or
+    /// context line, longer than this many bytes. Omitted lines still count
+    /// as matches.
+    #[arg(short = 'M', long, value_name = "NUM")]
+    max_columns: Option<usize>,
+
+    /// Print only the matched parts of matching lines, each on its own line.
+    /// Context lines aren't printed. With `-v`, print instead the parts of
+    /// every line between its matches, so a line with no match is printed
+    /// whole and one matched from end to end not at all.
+    #[arg(short = 'o', long, conflicts_with = "passthru")]
+    only_matching: bool,
+
+    /// With `-o`, print only this capture group of each match, by number or
+    /// by name. Group 0 is the whole match, and matches the group took no
+    /// part in are left out.
+    #[arg(
+        long,
+        value_name = "GROUP",
+        requires = "only_matching",
+        conflicts_with = "replace"
+    )]
+    only_matching_group: Option<String>,
+
+    /// Print each match on a matching line through this template instead
+    /// of the line. Placeholders are `{file}`, `{line}`, `{col}`, `{match}`,
+    /// `{text}` for the whole line, and `{N}` for capture group N; write
+    /// `{{` and `}}` for braces. A line found by `-v` is printed once, with
+    /// nothing for the match. Unknown placeholders are printed as they are.
+    #[arg(
+        long = "format",
+        value_name = "TEMPLATE",
+        conflicts_with = "only_matching"
+    )]
+    format_template: Option<String>,
+
+    /// With `-o`, print each distinct match once after everything has been
+    /// searched, with how many times it was found, like `sort | uniq -c`.
+    /// The most found come first, and ties are in alphabetical order.
+    #[arg(long, requires = "only_matching")]
+    sort_uniq: bool,
+
+    /// With `--sort-uniq`, print the least found first
+    #[arg(long, requires = "sort_uniq")]
+    asc: bool,
+
+    /// Put a tab rather than a space between the file name and numbers
+    /// before each line and the line itself, so lines start aligned
+    #[arg(short = 'T', long)]
+    initial_tab: bool,
+
+    /// Print a run of identical lines from the same file once, like `uniq`.
+    /// Counts still include every line. Has no effect on JSON or CSV output.
+    #[arg(long, visible_alias = "squeeze")]
+    dedup: bool,
+
+    /// Leave out the spaces and tabs at the start of each printed line.
+    /// Matching, and the columns printed by `--column`, still use the whole
+    /// line.
+    #[arg(long)]
+    trim: bool,
+
+    /// With `--max-columns`, print the start of each long line instead of
+    /// omitting it, cut short at a character boundary and ending with
+    /// ` [... truncated]`
+    #[arg(long, requires = "max_columns")]
+    max_columns_preview: bool,
+
+    /// Cut printed lines longer than this many bytes short, the same as
+    /// `--max-columns NUM --max-columns-preview`
+    #[arg(long, value_name = "NUM", conflicts_with = "max_columns")]
+    max_line_length: Option<usize>,
+
+    /// Name standard input this in the output instead of `(standard input)`.
+    /// Use `-H` to show it when standard input is the only input, which it
+    /// is when no paths are given; otherwise `-` among the paths reads it.
+    #[arg(long, value_name = "NAME", default_value = "(standard input)")]
+    label: String,
+
+    /// Follow every printed file name with a NUL byte instead of the usual
+    /// `:` or newline, for use with `xargs -0`
+    #[arg(short = 'Z', long)]
+    null: bool,
+
+    /// Search files in this order, rather than the order they are given or
+    /// found in. Every file is found before any is searched, and output
+    /// still comes out in this order when searching in parallel. Files whose
+    /// metadata can't be read go last.
+    #[arg(long, value_enum, value_name = "KEY", conflicts_with = "sortr")]
+    sort: Option<SortKey>,
+
+    /// Like `--sort`, but in descending order, such as the most recently
+    /// modified first
+    #[arg(long, value_enum, value_name = "KEY")]
+    sortr: Option<SortKey>,
+
+    /// How to print results. With `json`, options for how lines are
+    /// printed, such as `--column`, don't apply, and nothing is coloured.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+
+    /// Print results as JSON, one object per line, for other programs to
+    /// read. The same as `--output-format json`.
+    #[arg(long, conflicts_with = "output_format")]
+    json: bool,
+
+    /// Don't print messages about files that can't be opened or read. They
+    /// are still skipped, and still give exit status 2.
+    #[arg(short = 's', long)]
+    no_messages: bool,
+
+    /// Lines of context to print after each match, overriding `-C` for
+    /// that side
+    #[arg(
+        short = 'A',
+        long = "after",
+        visible_aliases = ["after-context", "post-context"],
+        value_name = "NUM",
+        value_parser = parse_context,
+        allow_negative_numbers = true
+    )]
+    after_context: Option<usize>,
+
+    /// Lines of context to print before each match, overriding `-C` for
+    /// that side
+    #[arg(
+        short = 'B',
+        long = "before",
+        visible_aliases = ["before-context", "pre-context"],
+        value_name = "NUM",
+        value_parser = parse_context,
+        allow_negative_numbers = true
+    )]
+    before_context: Option<usize>,
+
+    /// Lines of context to print on both sides of each match. As in grep,
+    /// `--` is printed between groups of lines that aren't next to each
+    /// other.
+    #[arg(
+        short = 'C',
+        long,
+        value_name = "NUM",
+        value_parser = parse_context,
+        allow_negative_numbers = true
+    )]
+    context: Option<usize>,
+
+    /// Print every line, highlighting the matches on those that match. Only
+    /// matching lines are counted, for `--total` and the exit status, and
+    /// context options make no difference.
+    #[arg(
+        long,
+        visible_alias = "passthrough",
+        conflicts_with_all = ["invert_match", "count", "count_matches", "files_with_matches", "multiline"]
+    )]
+    passthru: bool,
+
+    /// Let matches span more than one line. Classes such as `\s` or `[^)]`
+    /// match newlines, but `.` only does with `--multiline-dotall`. Each
+    /// file is read whole, so files over 256 MiB are skipped. Each match
+    /// is printed with every line it covers, and context is counted from the
+    /// first and last of them.
+    #[arg(short = 'U', long, conflicts_with = "invert_match")]
+    multiline: bool,
+
+    /// With `-U`, let `.` match newlines too, as in `BEGIN.*?END`
+    #[arg(long, requires = "multiline")]
+    multiline_dotall: bool,
+
+    /// Match `\w`, `\d`, `\s`, `\b` and case with ASCII rules only, which
+    /// is faster on large ASCII inputs. `.`, `\W` and negated classes such
+    /// as `[^a]` are refused then, as they could match part of a character;
+    /// write `(?u:.)` to keep Unicode for just that part.
+    #[arg(long, overrides_with = "unicode")]
+    no_unicode: bool,
+
+    /// Match with Unicode rules, as is the default. Overrides `--no-unicode`.
+    #[arg(long, overrides_with = "no_unicode")]
+    unicode: bool,
+
+    /// Use PCRE2 for the pattern, which supports look-around such as
+    /// `foo(?!bar)` and backreferences such as `(\w+) \1`. Only in builds
+    /// with the `pcre2` feature. The same as `--engine pcre2`.
+    #[arg(
+        short = 'P',
+        long,
+        conflicts_with_all = ["replace", "only_matching_group", "engine"]
+    )]
+    pcre2: bool,
+
+    /// Which regex engine to compile the pattern with
+    #[arg(long, value_enum, value_name = "ENGINE", default_value_t = RegexEngine::Auto)]
+    engine: RegexEngine,
+
+    /// The most memory the compiled regex may take, such as `10M` or `1G`.
+    /// Raise it for very large patterns, such as long generated lists of
+    /// alternatives.
+    #[arg(long, value_name = "SIZE", value_parser = parse_file_size)]
+    regex_size_limit: Option<u64>,
+
+    /// The most memory the regex engine's lazy DFA may use while searching,
+    /// such as `10M` or `1G`
+    #[arg(long, value_name = "SIZE", value_parser = parse_file_size)]
+    dfa_size_limit: Option<u64>,
+
+    /// Print notes to stderr about how the search is run
+    #[arg(long)]
+    debug: bool,
+
+    /// Ignore the `GREP_LITE_OPTIONS` environment variable. Otherwise the
+    /// options in it, split on whitespace unless quoted, are read as if
+    /// given before those on the command line, which take precedence: a
+    /// flag or option given in both takes its value from the command line,
+    /// while ones that can be repeated, such as `-e` or `--glob`, collect
+    /// the values from both.
+    #[arg(long)]
+    no_env_options: bool,
+
+    /// While recursing, keep a line on stderr showing how many files have
+    /// been found and where, cleared before the results are printed. Only
+    /// shown when stderr is a terminal.
+    #[arg(long)]
+    progress: bool,
+
+    /// Treat the input as records ended by NUL bytes rather than lines, as
+    /// written by `find -print0`. Records are numbered like lines, and each
+    /// one printed is followed by a NUL byte instead of a newline.
+    #[arg(long, conflicts_with = "multiline")]
+    null_data: bool,
+
+    /// Also decompress bzip2 and xz files without a recognised extension,
+    /// found by their first bytes. Files named `.gz`, `.bz2`, `.xz`, `.lzma`
+    /// or `.zip`, or starting with gzip or zip magic bytes, are always
+    /// decompressed, and every other file is searched as plain text. A
+    /// corrupt or truncated file is reported and the search moves on to the
+    /// next one.
+    #[arg(short = 'z', long, visible_alias = "search-zip")]
+    decompress: bool,
+
+    /// Number of threads used to search files, where 0 means one per CPU
+    /// (the default). Output is always printed in the order the files were
+    /// found, whatever the thread count, and `--threads 1` searches them one
+    /// at a time. Has no effect when only one file is searched, or with
+    /// `--quiet`, as those are always searched serially.
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Always memory-map files instead of reading them. By default only large
+    /// files are mapped.
+    #[arg(long, overrides_with = "no_mmap")]
+    mmap: bool,
+
+    /// Never memory-map files
+    #[arg(long, overrides_with = "mmap")]
+    no_mmap: bool,
+
+    /// Memory-map files of at least this many bytes. Ignored with `--mmap`
+    /// or `--no-mmap`.
+    #[arg(long, value_name = "BYTES", default_value_t = MMAP_THRESHOLD)]
+    mmap_threshold: u64,
+
+    /// Search inside every input file as a zip archive
+    #[arg(long)]
+    zip: bool,
+
+    /// Only search files of this type when recursing or inside archives, or
+    /// skip them with `!TYPE`. May be given more than once.
+    #[arg(long = "type", value_name = "TYPE", value_parser = parse_file_type)]
+    file_types: Vec<String>,
+
+    /// Skip files of this type when recursing or inside archives, as
+    /// `--type '!TYPE'` does. May be given more than once.
+    #[arg(long = "type-not", value_name = "TYPE", value_parser = parse_file_type)]
+    type_not: Vec<String>,
+
+    /// Only search files matching this glob when recursing or inside
+    /// archives, or skip them with `!GLOB`. May be given more than once, and
+    /// the last glob a path matches decides, so `-g '*.rs' -g '!*_test.rs'`
+    /// searches Rust files other than tests. Paths matching no glob are only
+    /// searched if every glob is a `!` one.
+    #[arg(short = 'g', long = "glob", value_name = "GLOB", value_parser = parse_glob)]
+    globs: Vec<String>,
+
+    /// Skip paths matching the gitignore-style patterns in this file when
+    /// recursing. Patterns are matched relative to each directory searched,
+    /// and support `*`, `**`, `!` to let a path back in, and a trailing `/`
+    /// to only match directories. May be given more than once.
+    #[arg(long, value_name = "PATH")]
+    ignore_file: Vec<PathBuf>,
+
+    /// Skip files larger than this when recursing, such as `500K`, `10M` or
+    /// `1G`. Files named on the command line are searched whatever their
+    /// size.
+    #[arg(long, value_name = "SIZE", value_parser = parse_file_size)]
+    max_filesize: Option<u64>,
+
+    /// Print every known file type and exit
+    #[arg(long)]
+    type_list: bool,
+
+    /// Print help
+    #[arg(long, action = clap::ArgAction::Help)]
+    help: Option<bool>,
+}
+
+// File types for `--type`, and the extensions each one covers
+const FILE_TYPES: &[(&str, &[&str])] = &[
+    ("c", &["c", "h", "cc", "cpp"]),
+    ("cpp", &["cpp", "cc", "cxx", "hpp", "hh", "hxx", "h"]),
+    ("css", &["css", "scss"]),
+    ("go", &["go"]),
+    ("html", &["htm", "html"]),
+    ("java", &["java"]),
+    ("js", &["js", "jsx", "mjs", "cjs"]),
+    ("json", &["json"]),
+    ("markdown", &["md", "markdown"]),
+    ("md", &["md", "markdown"]),
+    ("py", &["py", "pyx", "pxd"]),
+    ("python", &["py", "pyx", "pxd"]),
+    ("rust", &["rs"]),
+    ("sh", &["sh", "bash", "zsh"]),
+    ("toml", &["toml"]),
+    ("ts", &["ts", "tsx"]),
+    ("yaml", &["yaml", "yml"]),
+];
+
+// Checks that a `--type` value names a known file type, with an optional `!`
+fn parse_file_type(value: &str) -> std::result::Result<String, String> {
+    let name = value.strip_prefix('!').unwrap_or(value);
+    if FILE_TYPES.iter().any(|(known, _)| *known == name) {
+        Ok(value.to_string())
+    } else {
+        Err(format!("unknown file type '{}', see --type-list", name))
     }
+}
 
-    for input in inputs {
-        if args.recursive {
-            for entry in WalkDir::new(input).into_iter().filter_map(|e| e.ok()) {
-                if entry.file_type().is_file() {
-                    process_file(
-                        entry.path().to_str().unwrap(),
-                        &re,
-                        &args,
-                        is_multiple_files,
-                        after_context,
-                        before_context,
-                    )?;
-                }
+// Checks a glob for `--glob`, which may start with `!`
+fn parse_glob(value: &str) -> std::result::Result<String, String> {
+    let glob = value.strip_prefix('!').unwrap_or(value);
+    Glob::new(glob)
+        .map(|_| value.to_string())
+        .map_err(|e| e.to_string())
+}
+
+// Reads a number of lines for `-A`, `-B` or `-C`, which clap would otherwise
+// take `-1` for a flag or describe `abc` in its own terms
+fn parse_context(value: &str) -> std::result::Result<usize, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "invalid context value '{}', expected a number of lines",
+            value
+        )
+    })
+}
+
+// Splits a `--format` template into its text and placeholders. `{{` and `}}`
+// stand for braces, and a placeholder that isn't known is warned about and
+// kept as text, as is a `{` that's never closed.
+fn parse_template(template: &str) -> Vec<FormatPart> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = template;
+    while let Some(brace) = rest.find(['{', '}']) {
+        literal.push_str(&rest[..brace]);
+        let after = &rest[brace + 1..];
+        if rest[brace..].starts_with("{{") || rest[brace..].starts_with("}}") {
+            literal.push_str(&rest[brace..brace + 1]);
+            rest = &after[1..];
+            continue;
+        }
+        let name = match after.find('}') {
+            Some(end) if rest[brace..].starts_with('{') => &after[..end],
+            _ => {
+                literal.push_str(&rest[brace..brace + 1]);
+                rest = after;
+                continue;
             }
-        } else {
-            process_file(
-                input,
-                &re,
-                &args,
-                is_multiple_files,
-                after_context,
-                before_context,
-            )?;
+        };
+        let part = match name {
+            "file" => FormatPart::File,
+            "line" => FormatPart::Line,
+            "col" => FormatPart::Column,
+            "match" => FormatPart::Match,
+            "text" => FormatPart::Text,
+            _ => match name.parse() {
+                Ok(group) => FormatPart::Group(group),
+                Err(_) => {
+                    eprintln!(
+                        "grep-lite: unknown placeholder '{{{}}}' in --format, printed as it is",
+                        name
+                    );
+                    literal.push_str(&rest[brace..brace + name.len() + 2]);
+                    rest = &after[name.len() + 1..];
+                    continue;
+                }
+            },
+        };
+        if !literal.is_empty() {
+            parts.push(FormatPart::Literal(std::mem::take(&mut literal)));
         }
+        parts.push(part);
+        rest = &after[name.len() + 1..];
     }
-    Ok(())
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        parts.push(FormatPart::Literal(literal));
+    }
+    parts
 }
 
-// Process a single file
-fn process_file(
-    file_name: &str,
-    re: &Regex,
-    args: &Grep,
-    is_multiple_files: bool,
-    after_context: usize,
-    before_context: usize,
-) -> Result<()> {
-    let file = File::open(file_name)?;
-    let reader = BufReader::new(file);
-    process_line(
-        reader,
-        re,
-        args.invert_match,
-        args.count,
-        is_multiple_files,
-        file_name,
-        after_context,
-        before_context,
-    )?;
-    Ok(())
+// Reads a size for `--max-filesize` or the regex size limits: a number of bytes, optionally followed
+// by `K`, `M` or `G` for kibibytes, mebibytes or gibibytes
+fn parse_file_size(value: &str) -> std::result::Result<u64, String> {
+    let invalid = || {
+        format!(
+            "invalid size '{}', expected a number like 500K, 10M or 1G",
+            value
+        )
+    };
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&value[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&value[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|size| size.checked_mul(multiplier))
+        .ok_or_else(invalid)
 }
 
-fn process_line<T: BufRead + Sized>(
-    reader: T,
-    re: &Regex,
-    invert_match: bool,
-    count: bool,
-    is_multiple_files: bool,
-    file_name: &str,
-    after_context: usize,
-    before_context: usize,
-) -> Result<()> {
-    let mut current_count = 0;
+// Decides which files a recursive search looks at, and which files inside an
+// archive are searched, from the `--type` flags. Files must match one of the
+// included types, if any, and none of the excluded ones.
+#[derive(Clone)]
+struct FileTypeFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
 
-    let mut before_buffer: VecDeque<(usize, String)> = VecDeque::new();
-    let mut after_countdown = 0;
+impl FileTypeFilter {
+    fn new(file_types: &[String], type_not: &[String]) -> Self {
+        let mut include = GlobSetBuilder::new();
+        let mut exclude = GlobSetBuilder::new();
+        let mut has_include = false;
 
-    for (index, line) in reader.lines().enumerate() {
-        let line = match line {
-            Ok(line) => line,
-            Err(e) => {
-                println!("{}: Error reading file '{}'", file_name, e);
-                break;
+        let excluded = type_not.iter().map(|name| format!("!{}", name));
+        for file_type in file_types.iter().cloned().chain(excluded) {
+            let (builder, name) = match file_type.strip_prefix('!') {
+                Some(name) => (&mut exclude, name),
+                None => {
+                    has_include = true;
+                    (&mut include, file_type.as_str())
+                }
+            };
+            let extensions = FILE_TYPES
+                .iter()
+                .filter(|(known, _)| *known == name)
+                .flat_map(|(_, extensions)| extensions.iter());
+            for extension in extensions {
+                builder.add(
+                    Glob::new(&format!("*.{}", extension)).expect("file type globs are valid"),
+                );
             }
-        };
-        let match_found = re.is_match(&line) != invert_match;
+        }
 
-        if match_found {
-            for (before_index, before_line) in before_buffer.iter() {
-                print_line_with_highlighted_text(
-                    count,
-                    &mut current_count,
-                    before_line,
-                    *before_index,
-                    is_multiple_files,
-                    &file_name,
-                    &re,
-                    invert_match,
-                )?;
-            }
-            before_buffer.clear();
+        FileTypeFilter {
+            include: has_include.then(|| include.build().expect("file type globs are valid")),
+            exclude: exclude.build().expect("file type globs are valid"),
+        }
+    }
 
-            print_line_with_highlighted_text(
-                count,
-                &mut current_count,
-                &line,
-                index,
-                is_multiple_files,
-                &file_name,
-                &re,
-                invert_match,
-            )?;
-            after_countdown = after_context;
-        } else if after_countdown > 0 {
-            print_line_with_highlighted_text(
-                count,
-                &mut current_count,
-                &line,
-                index,
-                is_multiple_files,
-                &file_name,
-                &re,
-                invert_match,
-            )?;
-            after_countdown -= 1;
-        } else {
-            before_buffer.push_back((index, line));
-            if before_buffer.len() > before_context {
-                before_buffer.pop_front();
+    fn is_match(&self, path: &Path) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(path))
+            && !self.exclude.is_match(path)
+    }
+}
+
+// Paths a recursive search leaves out, from the gitignore-style patterns in
+// each `--ignore-file`. As in a `.gitignore`, the last pattern a path
+// matches decides, so one starting with `!` lets back in a path an earlier
+// one left out.
+struct IgnoreFilter {
+    globs: GlobSet,
+    // Whether each pattern, by index in `globs`, was given with `!`
+    negated: Vec<bool>,
+    // Whether each pattern ended in `/`, so only matches directories
+    dir_only: Vec<bool>,
+}
+
+impl IgnoreFilter {
+    // Reads the patterns from each of `ignore_files`, reporting any file
+    // that can't be read and any pattern that isn't a valid glob
+    fn new(ignore_files: &[PathBuf], no_messages: bool) -> Self {
+        let mut builder = GlobSetBuilder::new();
+        let mut negated = Vec::new();
+        let mut dir_only = Vec::new();
+        for ignore_file in ignore_files {
+            let contents = match fs::read_to_string(ignore_file) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    report_file_error(&ignore_file.display().to_string(), &e, no_messages);
+                    continue;
+                }
+            };
+            for (index, line) in contents.lines().enumerate() {
+                let line = line.trim_end();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                // `\#` and `\!` start patterns with those characters
+                let (pattern, is_negated) = match line.strip_prefix('!') {
+                    Some(pattern) => (pattern, true),
+                    None if line.starts_with("\\#") || line.starts_with("\\!") => {
+                        (&line[1..], false)
+                    }
+                    None => (line, false),
+                };
+                let (pattern, is_dir_only) = match pattern.strip_suffix('/') {
+                    Some(pattern) => (pattern, true),
+                    None => (pattern, false),
+                };
+                // A pattern with a `/` before its end is anchored to the
+                // directory searched, and one without matches at any depth
+                let glob = match pattern.strip_prefix('/') {
+                    Some(anchored) => anchored.to_string(),
+                    None if pattern.contains('/') => pattern.to_string(),
+                    None => format!("**/{}", pattern),
+                };
+                match GlobBuilder::new(&glob).literal_separator(true).build() {
+                    Ok(glob) => {
+                        builder.add(glob);
+                        negated.push(is_negated);
+                        dir_only.push(is_dir_only);
+                    }
+                    Err(e) => report_error(
+                        format_args!("{}:{}: {}", ignore_file.display(), index + 1, e),
+                        no_messages,
+                    ),
+                }
             }
         }
+        IgnoreFilter {
+            globs: builder.build().expect("each glob was built on its own"),
+            negated,
+            dir_only,
+        }
     }
 
-    if count {
-        if is_multiple_files {
-            println!("{}: {}", file_name, current_count);
-        } else {
-            println!("{}", current_count);
+    // Whether to leave out `path`, given relative to the directory searched
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.globs
+            .matches(path)
+            .into_iter()
+            .filter(|&index| is_dir || !self.dir_only[index])
+            .max()
+            .is_some_and(|last| !self.negated[last])
+    }
+}
+
+// Decides which files a recursive search looks at, and which files inside an
+// archive are searched, from the `--glob` flags. The last glob a path matches
+// decides whether it is searched, and a path matching none is searched unless
+// there are globs that include files.
+#[derive(Clone)]
+struct GlobFilter {
+    globs: GlobSet,
+    // Whether each glob, by index in `globs`, was given with `!`
+    excluded: Vec<bool>,
+    has_include: bool,
+}
+
+impl GlobFilter {
+    fn new(globs: &[String]) -> Self {
+        let mut builder = GlobSetBuilder::new();
+        let mut excluded = Vec::new();
+        for glob in globs {
+            let (glob, exclude) = match glob.strip_prefix('!') {
+                Some(glob) => (glob, true),
+                None => (glob.as_str(), false),
+            };
+            builder.add(Glob::new(glob).expect("globs are checked when parsed"));
+            excluded.push(exclude);
+        }
+        GlobFilter {
+            globs: builder.build().expect("globs are checked when parsed"),
+            has_include: excluded.contains(&false),
+            excluded,
         }
     }
 
-    Ok(())
+    // Paths are matched as they are printed, without a leading `./`
+    fn is_match(&self, path: &Path) -> bool {
+        let path = path.strip_prefix(".").unwrap_or(path);
+        match self.globs.matches(path).into_iter().max() {
+            Some(last) => !self.excluded[last],
+            None => !self.has_include,
+        }
+    }
 }
 
-fn print_line_with_highlighted_text(
-    count: bool,
-    current_count: &mut i32,
-    line: &str,
-    index: usize,
-    is_multiple_files: bool,
-    file_name: &str,
-    highlight_regex: &Regex,
+/// What files can be ordered by, for `--sort`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum SortKey {
+    /// In whatever order directories list their entries, which skips the
+    /// sorting `-r` otherwise does to give the same order every time
+    None,
+    /// By path, byte by byte
+    Path,
+    /// Least recently modified first
+    Modified,
+    /// Least recently created first
+    Created,
+    /// Smallest first
+    Size,
+}
+
+/// Which regex engine compiles the pattern, for `--engine`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum RegexEngine {
+    /// The regex crate, or PCRE2 for patterns using look-around or
+    /// backreferences when it's available
+    Auto,
+    /// Only the regex crate
+    Default,
+    /// Only PCRE2
+    Pcre2,
+}
+
+/// What to do with directories given as inputs, for `--directories`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum DirectoryAction {
+    /// Try to read them, which fails
+    Read,
+    /// Leave them out
+    Skip,
+    /// Search everything in them
+    Recurse,
+}
+
+/// When output is coloured, for `--color`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ColorMode {
+    /// Only when printing to a terminal
+    Auto,
+    /// Even when printing to a pipe or file
+    Always,
+    /// Never
+    Never,
+}
+
+/// The ways results can be printed, for `--output-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    /// Lines as grep prints them
+    Text,
+    /// The messages of `grep_lite::json`, one per line
+    Json,
+    /// A header, then a comma-separated row for each match and context line
+    Csv,
+}
+
+/// Whether lines and counts are prefixed with the name of their file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileNamePrefix {
+    /// Given with `-H`, or by default when more than one file is searched
+    Shown,
+    /// Left off by default, but still shown for archive members
+    Hidden,
+    /// Never shown, as asked for with `-h`
+    Suppressed,
+}
+
+/// A piece of a `--format` template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FormatPart {
+    /// Text printed as it is
+    Literal(String),
+    /// `{file}`
+    File,
+    /// `{line}`, the 1-based line number
+    Line,
+    /// `{col}`, the 1-based column of the match
+    Column,
+    /// `{match}`
+    Match,
+    /// `{text}`, the whole line
+    Text,
+    /// `{N}`, capture group N of the match
+    Group(usize),
+}
+
+/// How the lines found by a search are printed, and how file names are
+/// shown alongside them.
+#[derive(Debug, Clone, Copy)]
+struct OutputStyle<'a> {
+    file_name_prefix: FileNamePrefix,
+    /// Print the file name above its lines rather than on each of them
+    heading: bool,
+    /// End file names with a NUL byte
+    null: bool,
+    format: OutputFormat,
+    /// Leave out messages about files that can't be read
+    no_messages: bool,
+    /// The pattern's regex, for highlighting or replacing matches
+    regex: &'a CompiledRegex,
+    /// For telling which pattern each match is of, when there are several
+    search: &'a CompiledSearch,
+    /// Lines are printed because they don't match, so there's nothing in
+    /// them to highlight
     invert_match: bool,
-) -> Result<()> {
-    if count {
-        *current_count += 1;
-    } else {
-        let highlighted_line = if invert_match {
-            line.to_string()
+    /// Text to replace each match with, for `--replace`
+    replacement: Option<&'a str>,
+    /// Print the number of each line
+    line_number: bool,
+    /// Print the column of the first match on each line
+    column: bool,
+    /// Count that column in characters rather than bytes
+    column_chars: bool,
+    /// Print the byte offset of each line
+    byte_offset: bool,
+    /// Omit lines longer than this many bytes
+    max_columns: Option<usize>,
+    /// Cut long lines short rather than omitting them
+    max_columns_preview: bool,
+    /// Leave out leading whitespace
+    trim: bool,
+    /// Print a run of identical lines once
+    dedup: bool,
+    /// Start lines after a tab rather than a space
+    initial_tab: bool,
+    /// Print only the matched parts of lines
+    only_matching: bool,
+    /// What to print for each match instead of its line, for `--format`
+    template: Option<&'a [FormatPart]>,
+    /// The capture group printed of each match by `only_matching`
+    only_matching_group: usize,
+    /// Count the matched parts rather than printing them
+    sort_uniq: bool,
+    /// End printed lines with a NUL byte, for `--null-data`
+    null_data: bool,
+    /// Colour the output, as decided from `--color`
+    color: bool,
+    /// How each part of a printed line is coloured
+    colors: &'a ColorConfig,
+    /// What to call standard input, for `--label`
+    label: &'a str,
+    /// Print `--` between groups of lines that aren't next to each other,
+    /// as context was asked for
+    group_separator: bool,
+}
+
+impl<'a> OutputStyle<'a> {
+    /// The name to print for `file_name`, which is `--label` for standard
+    /// input.
+    fn input_name<'b>(&self, file_name: &'b str) -> &'b str
+    where
+        'a: 'b,
+    {
+        if file_name == STDIN_NAME {
+            self.label
+        } else {
+            file_name
+        }
+    }
+
+    /// Wraps `text` in the escape sequences for `sgr`, unless it's empty or
+    /// colour is turned off. Text spanning lines, from a multiline match, is
+    /// wrapped a line at a time so the colour never runs past a newline.
+    fn paint(&self, text: &str, sgr: &str) -> String {
+        if !self.color || sgr.is_empty() || text.is_empty() {
+            text.to_string()
         } else {
-            highlight_regex
-                .replace_all(line, |caps: &regex::Captures| {
-                    caps[0].bright_red().bold().to_string()
+            text.split('\n')
+                .map(|part| {
+                    if part.is_empty() {
+                        String::new()
+                    } else {
+                        format!("\x1b[{}m{}\x1b[0m", sgr, part)
+                    }
                 })
-                .to_string()
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    }
+}
+
+/// The SGR parameters (such as `01;31`) used to colour each part of the
+/// output, set with the `GREP_COLORS` environment variable as in GNU grep.
+/// An empty value leaves that part uncoloured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ColorConfig {
+    /// Matched text in a matching line
+    selected_match: String,
+    /// Matched text in a context line
+    context_match: String,
+    /// File names
+    file_name: String,
+    /// Line numbers of matching lines
+    line_number: String,
+    /// Line numbers of context lines
+    context_line_number: String,
+    /// The rest of a context line
+    context: String,
+    /// Separators between the fields before a line, and the `--` between
+    /// groups of lines
+    separator: String,
+}
+
+impl Default for ColorConfig {
+    fn default() -> Self {
+        ColorConfig {
+            selected_match: "1;91".to_string(),
+            context_match: "1;91".to_string(),
+            file_name: "35".to_string(),
+            line_number: "32".to_string(),
+            context_line_number: "2;32".to_string(),
+            context: String::new(),
+            separator: "36".to_string(),
+        }
+    }
+}
+
+// Reads a `GREP_COLORS` value such as `ms=01;31:fn=35:ln=32:cx=:se=36`,
+// keeping the default for any key that's left out. `mt` sets both `ms` and
+// `mc`, and `ln` numbers context lines too. Unknown keys, and values that
+// aren't SGR parameters, are ignored.
+fn parse_grep_colors(s: &str) -> ColorConfig {
+    let mut colors = ColorConfig::default();
+    for entry in s.split(':') {
+        let Some((key, value)) = entry.split_once('=') else {
+            continue;
         };
+        if !value.chars().all(|c| c.is_ascii_digit() || c == ';') {
+            continue;
+        }
+        let value = value.to_string();
+        match key {
+            "mt" => {
+                colors.selected_match = value.clone();
+                colors.context_match = value;
+            }
+            "ms" => colors.selected_match = value,
+            "mc" => colors.context_match = value,
+            "fn" => colors.file_name = value,
+            "ln" => {
+                colors.line_number = value.clone();
+                colors.context_line_number = value;
+            }
+            "cx" => colors.context = value,
+            "se" => colors.separator = value,
+            _ => {}
+        }
+    }
+    colors
+}
 
-        if is_multiple_files {
-            print!("{}:{}: ", file_name, index + 1);
-        } else {
-            print!("{}: ", index + 1);
+// Set once any heading has been printed, so the next one is set apart from
+// the lines before it
+static PRINTED_HEADING: AtomicBool = AtomicBool::new(false);
+
+// Set once any line has been printed, so without headings the first group of
+// lines from the next file is set apart from it by `--`
+static PRINTED_LINE: AtomicBool = AtomicBool::new(false);
+
+// Set once any file couldn't be searched, so the exit status can say so
+static FOUND_ERROR: AtomicBool = AtomicBool::new(false);
+
+// Where the results are written: stdout, or the file given with `--output`
+enum OutputSink {
+    Stdout(io::Stdout),
+    File(BufWriter<File>),
+}
+
+impl Write for OutputSink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            OutputSink::Stdout(stdout) => stdout.write(buf),
+            OutputSink::File(file) => file.write(buf),
         }
-        println!("{}", highlighted_line);
     }
 
-    Ok(())
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            OutputSink::Stdout(stdout) => stdout.flush(),
+            OutputSink::File(file) => file.flush(),
+        }
+    }
+}
+
+// Prints events to the output in the usual grep-lite format
+struct StdoutSink<'a> {
+    file_name: Cow<'a, str>,
+    style: OutputStyle<'a>,
+    out: &'a mut dyn Write,
+    // Whether this file's heading has been printed yet. It is only printed
+    // before the first line, so files without matches get none. With
+    // `--json`, whether its `begin` message has been printed.
+    printed_heading: bool,
+    // What has been counted in this file so far, for its `end` message
+    count: usize,
+    // What the search found, for `--stats`
+    stats: SearchStats,
+    // The last line printed, for `--dedup`
+    last_line: Option<String>,
+    // The index of the last line printed from this file, so a group of
+    // lines that doesn't follow on from it gets a `--` before it
+    last_index: Option<usize>,
+    // How many times each match was found, for `--sort-uniq`
+    tally: HashMap<String, usize>,
+    // The bytes of the next line, when it isn't valid UTF-8, for `--json`
+    line_bytes: Option<Vec<u8>>,
+    // The first error writing the output. Nothing more is written after it,
+    // and `finish` passes it on.
+    error: Option<io::Error>,
+}
+
+impl<'a> StdoutSink<'a> {
+    fn new(file_name: &'a str, style: OutputStyle<'a>, out: &'a mut dyn Write) -> Self {
+        StdoutSink {
+            file_name: file_name.into(),
+            style,
+            out,
+            printed_heading: false,
+            count: 0,
+            stats: SearchStats::default(),
+            last_line: None,
+            last_index: None,
+            tally: HashMap::new(),
+            line_bytes: None,
+            error: None,
+        }
+    }
+
+    fn shows_file_name(&self) -> bool {
+        self.style.file_name_prefix == FileNamePrefix::Shown
+    }
+
+    // Ends the file's output once it has all been printed
+    fn finish(&mut self) -> Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.style.format == OutputFormat::Json && self.printed_heading {
+            print_json(self.out, &json::Message::end(&self.file_name, self.count))?;
+            self.printed_heading = false;
+            self.count = 0;
+        }
+        Ok(())
+    }
+
+    // Prints a CSV row for each match on a matching line, or a single row for
+    // a line with nothing in it to point at
+    fn emit_csv(&mut self, event: MatchEvent) -> Result<()> {
+        match event {
+            MatchEvent::Line { index, line, .. } => {
+                let mut found = false;
+                if !self.style.invert_match {
+                    for range in self.style.regex.find_iter(&line) {
+                        print_csv_row(self.out, &self.file_name, index, Some(range), &line, false)?;
+                        found = true;
+                    }
+                }
+                if !found {
+                    print_csv_row(self.out, &self.file_name, index, None, &line, false)?;
+                }
+            }
+            MatchEvent::Context { index, line, .. } => {
+                print_csv_row(self.out, &self.file_name, index, None, &line, true)?
+            }
+            MatchEvent::ReadError(e) => report_error(
+                format_args!("{}: {}", self.file_name, e),
+                self.style.no_messages,
+            ),
+            MatchEvent::Member(file_name) => self.file_name = file_name.into(),
+            MatchEvent::Stats(stats) => self.stats += stats,
+            // Rows give the line's text, with U+FFFD for any invalid bytes
+            MatchEvent::LineBytes(_) => {}
+            // Refused in `main`, as they have no lines to give rows for
+            MatchEvent::Count(_) | MatchEvent::FileMatched => {}
+        }
+        Ok(())
+    }
+
+    // Prints the messages for `--json`, starting the file with `begin`
+    // before anything else from it
+    fn emit_json(&mut self, event: MatchEvent) -> Result<()> {
+        if let MatchEvent::Member(file_name) = event {
+            self.finish()?;
+            self.file_name = file_name.into();
+            return Ok(());
+        }
+        if let MatchEvent::Stats(stats) = event {
+            self.stats += stats;
+            return Ok(());
+        }
+        if let MatchEvent::LineBytes(bytes) = event {
+            self.line_bytes = Some(bytes);
+            return Ok(());
+        }
+        if let MatchEvent::ReadError(e) = event {
+            report_error(
+                format_args!("{}: {}", self.file_name, e),
+                self.style.no_messages,
+            );
+            return Ok(());
+        }
+        if !self.printed_heading {
+            print_json(self.out, &json::Message::begin(&self.file_name))?;
+            self.printed_heading = true;
+        }
+        match event {
+            MatchEvent::Line {
+                index,
+                offset,
+                line,
+            } => {
+                let regex = (!self.style.invert_match).then_some(self.style.regex);
+                let message = match self.line_bytes.take() {
+                    Some(bytes) => json::Message::matched_bytes(
+                        &self.file_name,
+                        index + 1,
+                        offset,
+                        &bytes,
+                        regex,
+                    ),
+                    None => {
+                        json::Message::matched(&self.file_name, index + 1, offset, &line, regex)
+                    }
+                };
+                print_json(self.out, &message)?;
+                self.count += 1;
+            }
+            MatchEvent::Context {
+                index,
+                offset,
+                line,
+            } => {
+                let message = match self.line_bytes.take() {
+                    Some(bytes) => {
+                        json::Message::context_bytes(&self.file_name, index + 1, offset, &bytes)
+                    }
+                    None => json::Message::context(&self.file_name, index + 1, offset, &line),
+                };
+                print_json(self.out, &message)?
+            }
+            MatchEvent::Count(count) => self.count = count,
+            MatchEvent::FileMatched => self.count = 1,
+            MatchEvent::ReadError(_)
+            | MatchEvent::Member(_)
+            | MatchEvent::Stats(_)
+            | MatchEvent::LineBytes(_) => {}
+        }
+        Ok(())
+    }
+}
+
+impl Sink for StdoutSink<'_> {
+    fn emit(&mut self, event: MatchEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        let printed = match self.style.format {
+            OutputFormat::Json => self.emit_json(event),
+            OutputFormat::Csv => self.emit_csv(event),
+            OutputFormat::Text => self.emit_text(event),
+        };
+        if let Err(e) = printed {
+            self.error = Some(e);
+        }
+    }
+}
+
+impl StdoutSink<'_> {
+    // Prints an event as grep would, for the text format
+    fn emit_text(&mut self, event: MatchEvent) -> Result<()> {
+        match event {
+            MatchEvent::Line {
+                index,
+                offset,
+                ref line,
+            }
+            | MatchEvent::Context {
+                index,
+                offset,
+                ref line,
+            } => {
+                let is_context = matches!(event, MatchEvent::Context { .. });
+                if self.style.only_matching && is_context && !self.style.invert_match {
+                    return Ok(());
+                }
+                if let Some(template) = self.style.template {
+                    if !is_context {
+                        print_with_template(
+                            self.out,
+                            line,
+                            index,
+                            &self.file_name,
+                            template,
+                            &self.style,
+                        )?;
+                    }
+                    return Ok(());
+                }
+                if self.style.sort_uniq {
+                    for (_, text) in matched_texts(line, &self.style) {
+                        *self.tally.entry(text).or_default() += 1;
+                    }
+                    return Ok(());
+                }
+                let heading = self.style.heading && self.shows_file_name();
+                // As in grep, a group is set apart from the one before it in
+                // this file, or without headings from the last file's lines
+                let starts_group = self.style.group_separator
+                    && match self.last_index {
+                        Some(last) => index > last + 1,
+                        None => !heading && PRINTED_LINE.load(Ordering::Relaxed),
+                    };
+                // A multiline match covers every line it spans
+                self.last_index = Some(index + line.matches('\n').count());
+                if self.style.dedup {
+                    if !starts_group && self.last_line.as_ref() == Some(line) {
+                        return Ok(());
+                    }
+                    self.last_line = Some(line.clone());
+                }
+                if heading && !self.printed_heading {
+                    if PRINTED_HEADING.swap(true, Ordering::Relaxed) {
+                        writeln!(self.out)?;
+                    }
+                    print_file_name(
+                        self.out,
+                        &self
+                            .style
+                            .paint(&self.file_name, &self.style.colors.file_name),
+                        "\n",
+                        self.style.null,
+                    )?;
+                    self.printed_heading = true;
+                }
+                if starts_group {
+                    let separator = self.style.paint("--", &self.style.colors.separator);
+                    writeln!(self.out, "{}", separator)?;
+                }
+                PRINTED_LINE.store(true, Ordering::Relaxed);
+                // Shown on the line itself unless it's in a heading
+                let file_name = (self.shows_file_name() && !heading).then_some(&*self.file_name);
+                print_line_with_highlighted_text(
+                    self.out,
+                    line,
+                    index,
+                    offset,
+                    is_context,
+                    file_name,
+                    &self.style,
+                )?
+            }
+            MatchEvent::Count(count) => {
+                if self.shows_file_name() {
+                    let separator = self.style.paint(":", &self.style.colors.separator);
+                    print_file_name(
+                        self.out,
+                        &self
+                            .style
+                            .paint(&self.file_name, &self.style.colors.file_name),
+                        &format!("{} ", separator),
+                        self.style.null,
+                    )?;
+                }
+                writeln!(self.out, "{}", count)?;
+            }
+            MatchEvent::ReadError(e) => report_error(
+                format_args!("{}: {}", self.file_name, e),
+                self.style.no_messages,
+            ),
+            MatchEvent::FileMatched => print_file_name(
+                self.out,
+                &self
+                    .style
+                    .paint(&self.file_name, &self.style.colors.file_name),
+                "\n",
+                self.style.null,
+            )?,
+            MatchEvent::Stats(stats) => self.stats += stats,
+            // Lines are printed as text, with U+FFFD for any invalid bytes
+            MatchEvent::LineBytes(_) => {}
+            MatchEvent::Member(file_name) => {
+                // Archive members are named unless `-h` was given, as an
+                // archive holds many files
+                self.file_name = file_name.into();
+                self.printed_heading = false;
+                self.last_line = None;
+                self.last_index = None;
+                if self.style.file_name_prefix == FileNamePrefix::Hidden {
+                    self.style.file_name_prefix = FileNamePrefix::Shown;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn main() -> ExitCode {
+    // Failing to write the results, as on a full disk, ends the search. A
+    // reader that stops early, like `head`, closes the pipe, which is no error.
+    match run() {
+        Ok(code) => code,
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("grep-lite: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run() -> Result<ExitCode> {
+    let started = Instant::now();
+    let mut args = Grep::parse_from(args_with_env_options());
+    if args.debug && !args.no_env_options {
+        if let Ok(options) = env::var(ENV_OPTIONS) {
+            eprintln!("grep-lite: using {}={}", ENV_OPTIONS, options);
+        }
+    }
+    // With `-e`, what looked like the pattern is the first input
+    if !args.regexp.is_empty() {
+        if let Some(input) = args.pattern.take() {
+            args.inputs.insert(0, input);
+        }
+    }
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(io::Error::other)?;
+    }
+
+    if args.type_list {
+        for (name, extensions) in FILE_TYPES {
+            let globs: Vec<String> = extensions.iter().map(|ext| format!("*.{}", ext)).collect();
+            println!("{}: {}", name, globs.join(", "));
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut config = search_config(&args);
+    // PCRE2 can't replace or pick out capture groups, so `--engine auto`
+    // only turns to it for patterns the regex crate would refuse
+    let needs_regex_crate = args.replace.is_some() || args.only_matching_group.is_some();
+    if config.pcre2 && needs_regex_crate {
+        eprintln!("grep-lite: --replace and --only-matching-group can't be used with PCRE2");
+        return Ok(ExitCode::from(2));
+    }
+    if args.engine == RegexEngine::Auto
+        && cfg!(feature = "pcre2")
+        && !config.pcre2
+        && !needs_regex_crate
+        && config.needs_pcre2()
+    {
+        config.pcre2 = true;
+        if args.debug {
+            eprintln!("grep-lite: using PCRE2, as the pattern uses look-around or backreferences");
+        }
+    }
+
+    let search = match CompiledSearch::new(config) {
+        Ok(search) => search,
+        Err(GrepError::Regex(regex::Error::CompiledTooBig(limit))) => {
+            eprintln!(
+                "grep-lite: the pattern is too big, compiling to more than the limit of {} bytes; \
+                 raise it with --regex-size-limit",
+                limit
+            );
+            return Ok(ExitCode::from(2));
+        }
+        Err(e) => {
+            eprintln!("grep-lite: {}", e);
+            let needs_unicode = e.to_string().contains("invalid UTF-8")
+                || e.to_string().contains("Unicode not allowed");
+            if args.no_unicode && needs_unicode {
+                eprintln!(
+                    "grep-lite: with --no-unicode, write `(?u:...)` around any part of the \
+                     pattern needing Unicode, such as `(?u:.)`"
+                );
+            }
+            return Ok(ExitCode::from(2));
+        }
+    };
+
+    if let Some(group) = &args.only_matching_group {
+        if capture_group(search.regex(), group).is_none() {
+            eprintln!("grep-lite: the pattern has no capture group '{}'", group);
+            return Ok(ExitCode::from(2));
+        }
+    }
+
+    let format = output_format(&args);
+    if format == OutputFormat::Json && args.replace.is_some() {
+        eprintln!("grep-lite: --replace can't be used with JSON output");
+        return Ok(ExitCode::from(2));
+    }
+    if format == OutputFormat::Csv
+        && (args.replace.is_some() || args.count || args.count_matches || args.files_with_matches)
+    {
+        eprintln!("grep-lite: CSV output can't be used with --replace, -c, --count-matches or -l");
+        return Ok(ExitCode::from(2));
+    }
+    if format != OutputFormat::Text && args.sort_uniq {
+        eprintln!("grep-lite: --sort-uniq can only be used with text output");
+        return Ok(ExitCode::from(2));
+    }
+    if format != OutputFormat::Text && args.format_template.is_some() {
+        eprintln!("grep-lite: --format can only be used with text output");
+        return Ok(ExitCode::from(2));
+    }
+    let template = args.format_template.as_deref().map(parse_template);
+
+    let colors = env::var("GREP_COLORS")
+        .map(|value| parse_grep_colors(&value))
+        .unwrap_or_default();
+
+    // Like grep, `-r` with nothing to search searches the current directory,
+    // naming files relative to it
+    let recursive = args.recursive || args.directories == DirectoryAction::Recurse;
+    if args.max_depth.is_some() && !recursive {
+        eprintln!("grep-lite: --max-depth needs -r or -d recurse");
+        return Ok(ExitCode::from(2));
+    }
+    let searches_cwd = recursive && args.inputs.is_empty();
+    let inputs = if searches_cwd {
+        vec![".".to_string()]
+    } else {
+        args.inputs.clone()
+    };
+
+    let file_type_filter = FileTypeFilter::new(&args.file_types, &args.type_not);
+    let glob_filter = GlobFilter::new(&args.globs);
+    // Files inside archives are picked out by the same filters as files on disk
+    let search = {
+        let (file_type_filter, glob_filter) = (file_type_filter.clone(), glob_filter.clone());
+        search.with_member_filter(move |path| {
+            file_type_filter.is_match(path) && glob_filter.is_match(path)
+        })
+    };
+    let ignore_filter = IgnoreFilter::new(&args.ignore_file, args.no_messages);
+    let mut summary = Summary::default();
+
+    let reads_stdin = inputs.is_empty() || inputs.iter().any(|input| input == STDIN_NAME);
+    if args.in_place && reads_stdin {
+        eprintln!("grep-lite: --in-place needs files to edit, and can't edit stdin");
+        return Ok(ExitCode::from(2));
+    }
+    if args.diff && reads_stdin {
+        eprintln!("grep-lite: --diff needs files to compare, and can't read stdin");
+        return Ok(ExitCode::from(2));
+    }
+    if recursive && reads_stdin {
+        eprintln!("grep-lite: standard input can't be searched recursively");
+        return Ok(ExitCode::from(2));
+    }
+
+    // With no paths, standard input is the only input
+    let inputs = if inputs.is_empty() {
+        vec![STDIN_NAME.to_string()]
+    } else {
+        inputs
+    };
+
+    // Gather every file up front so they can all be searched in parallel
+    let mut files = Vec::new();
+    let mut progress =
+        (args.progress && recursive && io::stderr().is_terminal()).then(Progress::default);
+    for input in &inputs {
+        if !recursive && args.directories == DirectoryAction::Skip && Path::new(input).is_dir() {
+            continue;
+        }
+        if recursive {
+            let walk = WalkDir::new(input)
+                .max_depth(args.max_depth.unwrap_or(usize::MAX))
+                .follow_links(args.follow);
+            // Walk in the same order every time, unless told not to bother
+            let walk = if matches!(args.sort.or(args.sortr), Some(SortKey::None)) {
+                walk
+            } else {
+                walk.sort_by_file_name()
+            };
+            files.extend(
+                walk.into_iter()
+                    // Ignored directories aren't walked into at all
+                    .filter_entry(|entry| {
+                        let path = entry.path().strip_prefix(input).unwrap_or(entry.path());
+                        entry.depth() == 0
+                            || !ignore_filter.is_ignored(path, entry.file_type().is_dir())
+                    })
+                    .filter_map(|entry| match entry {
+                        Ok(entry) => Some(entry),
+                        Err(e) => {
+                            report_error(e, args.no_messages);
+                            None
+                        }
+                    })
+                    .filter(|entry| entry.file_type().is_file())
+                    .inspect(|entry| {
+                        if let Some(progress) = &mut progress {
+                            progress.found(entry.path());
+                        }
+                    })
+                    .filter(|entry| file_type_filter.is_match(entry.path()))
+                    .filter(|entry| glob_filter.is_match(entry.path()))
+                    .filter(|entry| match (args.max_filesize, entry.metadata()) {
+                        (Some(max), Ok(metadata)) => metadata.len() <= max,
+                        _ => true,
+                    })
+                    .filter_map(|entry| {
+                        let path = entry.path();
+                        let path = if searches_cwd {
+                            path.strip_prefix(".").unwrap_or(path)
+                        } else {
+                            path
+                        };
+                        // Names are printed and matched as text, so a file
+                        // whose name isn't UTF-8 is reported and left out
+                        match path.to_str() {
+                            Some(path) => Some(path.to_string()),
+                            None => {
+                                report_error(
+                                    format_args!(
+                                        "{}: skipping a file name that isn't valid UTF-8",
+                                        path.display()
+                                    ),
+                                    args.no_messages,
+                                );
+                                None
+                            }
+                        }
+                    }),
+            );
+        } else {
+            files.push(input.clone());
+        }
+    }
+    if let Some(progress) = &progress {
+        progress.clear();
+    }
+
+    // Overlapping directories, or links followed with `-L`, can lead to the
+    // same file more than once, so each is only searched the first time.
+    // Paths that can't be resolved are compared as given.
+    if recursive && (inputs.len() > 1 || args.follow) {
+        let mut seen = HashSet::new();
+        files.retain(|file| {
+            seen.insert(fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file)))
+        });
+    }
+
+    // Searching the file the results go to would find them again as it grows,
+    // and opening it for the results would empty it, so that's refused before
+    // the output is opened
+    if let Some(output) = args
+        .output
+        .as_ref()
+        .and_then(|path| fs::canonicalize(path).ok())
+    {
+        if let Some(file) = files
+            .iter()
+            .find(|file| fs::canonicalize(file).is_ok_and(|path| path == output))
+        {
+            eprintln!("grep-lite: {}: input file is also the output", file);
+            return Ok(ExitCode::from(2));
+        }
+    }
+
+    let mut out = match &args.output {
+        Some(path) => match File::create(path) {
+            Ok(file) => OutputSink::File(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("grep-lite: can't create {}: {}", path.display(), e);
+                return Ok(ExitCode::from(2));
+            }
+        },
+        None => OutputSink::Stdout(io::stdout()),
+    };
+    if format == OutputFormat::Csv && !args.quiet {
+        writeln!(out, "{}", CSV_HEADER)?;
+    }
+
+    if let Some(key) = args.sort {
+        sort_files(&mut files, key, false, args.no_messages);
+    } else if let Some(key) = args.sortr {
+        sort_files(&mut files, key, true, args.no_messages);
+    }
+
+    if let (true, Some(replacement)) = (args.diff, &args.replace) {
+        let style = output_style(&args, &search, &colors, None, true);
+        let code = print_replacement_diffs(&mut out, &files, &search, replacement, &style)?;
+        out.flush()?;
+        return Ok(code);
+    }
+
+    if let (true, Some(replacement)) = (args.in_place, &args.replace) {
+        return Ok(edit_files_in_place(
+            &files,
+            &search,
+            replacement,
+            args.backup.as_deref(),
+            args.dry_run,
+            args.no_messages,
+        ));
+    }
+
+    // Any recursive search may find many files, so name them even if it
+    // didn't. As in grep, it's the inputs given that count, including
+    // directories left out by `-d skip`.
+    let style = output_style(
+        &args,
+        &search,
+        &colors,
+        template.as_deref(),
+        inputs.len() > 1 || recursive,
+    );
+
+    search_files_in_parallel(&files, &search, args.threads, style, &mut summary, &mut out)?;
+
+    let total = summary.count;
+    if format == OutputFormat::Json && args.stats {
+        print_json(
+            &mut out,
+            &json::Message::summary_with_totals(total, summary.totals(started.elapsed())),
+        )?;
+    } else if format == OutputFormat::Json && !args.quiet {
+        print_json(&mut out, &json::Message::summary(total))?;
+    } else {
+        if args.sort_uniq && !args.quiet {
+            summary.print_tally(&mut out, args.asc)?;
+        }
+        if args.total && !args.quiet {
+            writeln!(out, "total: {}", total)?;
+        }
+        if args.summary && !args.quiet {
+            summary.print_summary_line(&mut out, args.count_matches)?;
+        }
+        if args.stats {
+            summary.print(started.elapsed());
+        }
+    }
+    out.flush()?;
+
+    Ok(exit_code(total > 0, args.quiet))
+}
+
+// The environment variable holding options to read before the command line's
+const ENV_OPTIONS: &str = "GREP_LITE_OPTIONS";
+
+// The command line with the options from `GREP_LITE_OPTIONS` put in front of
+// its own, unless it has `--no-env-options` before any `--`
+fn args_with_env_options() -> impl Iterator<Item = OsString> {
+    let mut args = env::args_os();
+    let program = args.next();
+    let args: Vec<OsString> = args.collect();
+    let disabled = args
+        .iter()
+        .take_while(|arg| *arg != "--")
+        .any(|arg| arg == "--no-env-options");
+    let env_options = match env::var(ENV_OPTIONS) {
+        Ok(options) if !disabled => split_options(&options),
+        _ => Vec::new(),
+    };
+    program
+        .into_iter()
+        .chain(env_options.into_iter().map(OsString::from))
+        .chain(args)
+}
+
+// Splits `options` on whitespace as a shell would, keeping together what's
+// inside single or double quotes. A backslash outside single quotes takes
+// the next character as it is, and a quote left open runs to the end.
+fn split_options(options: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // Whether a word has been started, as `""` is an empty one
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = options.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some('"') | None, '\\') => {
+                in_word = true;
+                word.extend(chars.next());
+            }
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                in_word = true;
+                quote = Some(c);
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+// What was found across every input, for `--total`, `--stats` and the exit
+// status
+#[derive(Debug, Default)]
+struct Summary {
+    // The lines counted, as printed by `--total`
+    count: usize,
+    files_searched: usize,
+    files_matched: usize,
+    // Only filled in with `--stats`
+    stats: SearchStats,
+    // Only filled in with `--sort-uniq`
+    tally: HashMap<String, usize>,
+}
+
+impl Summary {
+    // Adds in a file that was searched, once its sink is done with it
+    fn add(&mut self, count: usize, sink: StdoutSink) {
+        self.count += count;
+        self.files_searched += 1;
+        if count > 0 {
+            self.files_matched += 1;
+        }
+        self.stats += sink.stats;
+        for (text, found) in sink.tally {
+            *self.tally.entry(text).or_default() += found;
+        }
+    }
+
+    // Prints the distinct matches for `--sort-uniq`, most found first unless
+    // `ascending`, and alphabetically among those found as often
+    fn print_tally(&self, out: &mut dyn Write, ascending: bool) -> Result<()> {
+        let mut tally: Vec<(&String, &usize)> = self.tally.iter().collect();
+        tally.sort_by(|(a_text, a_found), (b_text, b_found)| {
+            let by_count = if ascending {
+                a_found.cmp(b_found)
+            } else {
+                b_found.cmp(a_found)
+            };
+            by_count.then_with(|| a_text.cmp(b_text))
+        });
+        for (text, found) in tally {
+            writeln!(out, "{:>7} {}", found, text)?;
+        }
+        Ok(())
+    }
+
+    // Prints the line for `--summary`, where the count is of matches rather
+    // than lines with `--count-matches`
+    fn print_summary_line(&self, out: &mut dyn Write, count_matches: bool) -> Result<()> {
+        let plural = |n: usize, one: &'static str, many: &'static str| {
+            if n == 1 {
+                one
+            } else {
+                many
+            }
+        };
+        let (one, many) = if count_matches {
+            ("match", "matches")
+        } else {
+            ("line", "lines")
+        };
+        writeln!(
+            out,
+            "matched {} {} across {} {}",
+            self.count,
+            plural(self.count, one, many),
+            self.files_matched,
+            plural(self.files_matched, "file", "files")
+        )
+    }
+
+    fn totals(&self, elapsed: Duration) -> json::Totals {
+        json::Totals {
+            matched_lines: self.stats.matched_lines,
+            matches: self.stats.matches,
+            files_with_matches: self.files_matched,
+            files_searched: self.files_searched,
+            lines_searched: self.stats.lines_searched,
+            bytes_searched: self.stats.bytes_searched,
+            elapsed_micros: elapsed.as_micros() as u64,
+        }
+    }
+
+    // Prints the block for `--stats` to stderr, so it can be kept apart
+    // from the results
+    fn print(&self, elapsed: Duration) {
+        eprintln!("{} matched lines", self.stats.matched_lines);
+        eprintln!("{} matches", self.stats.matches);
+        eprintln!("{} files contained matches", self.files_matched);
+        eprintln!("{} files searched", self.files_searched);
+        eprintln!("{} lines searched", self.stats.lines_searched);
+        eprintln!("{} bytes searched", self.stats.bytes_searched);
+        eprintln!("{:.6} seconds", elapsed.as_secs_f64());
+    }
+}
+
+// How often the `--progress` line may be redrawn
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// The longest path shown by `--progress`, so the line never wraps and can't
+// be drawn over. Longer paths are cut from the front.
+const PROGRESS_PATH_WIDTH: usize = 60;
+
+// The line for `--progress`, redrawn in place on stderr as the walk finds
+// files
+#[derive(Debug, Default)]
+struct Progress {
+    files: usize,
+    // When the line was last drawn, if it has been
+    drawn_at: Option<Instant>,
+}
+
+impl Progress {
+    // Counts a file the walk found at `path`, and shows it unless the line
+    // was drawn too recently
+    fn found(&mut self, path: &Path) {
+        self.files += 1;
+        if self
+            .drawn_at
+            .is_some_and(|drawn_at| drawn_at.elapsed() < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        self.drawn_at = Some(Instant::now());
+        let path = path.display().to_string();
+        let skipped = path.chars().count().saturating_sub(PROGRESS_PATH_WIDTH);
+        let path = match path.char_indices().nth(skipped) {
+            Some((start, _)) if skipped > 0 => format!("...{}", &path[start..]),
+            _ => path,
+        };
+        let mut stderr = io::stderr().lock();
+        // Losing a line of progress doesn't matter
+        let _ = write!(stderr, "\r\x1b[K{} files found: {}", self.files, path);
+        let _ = stderr.flush();
+    }
+
+    // Rubs the line out, once the walk is done
+    fn clear(&self) {
+        if self.drawn_at.is_some() {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+// Like grep, exit with 1 when nothing matched, and 2 when a file couldn't be
+// searched. With `--quiet`, a match is all that matters.
+fn exit_code(matched: bool, quiet: bool) -> ExitCode {
+    if FOUND_ERROR.load(Ordering::Relaxed) && !(quiet && matched) {
+        ExitCode::from(2)
+    } else if matched {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    }
+}
+
+// Reports a file that couldn't be searched, unless `--no-messages` was given,
+// and remembers it for the exit status
+fn report_error(message: impl fmt::Display, no_messages: bool) {
+    if !no_messages {
+        eprintln!("grep-lite: {}", message);
+    }
+    FOUND_ERROR.store(true, Ordering::Relaxed);
+}
+
+// Reports a file that couldn't be opened or read as `name: reason`, like
+// grep, leaving off the OS error code
+fn report_file_error(file_name: &str, e: &io::Error, no_messages: bool) {
+    let message = e.to_string();
+    let reason = match e.raw_os_error() {
+        Some(code) => message
+            .strip_suffix(&format!(" (os error {})", code))
+            .unwrap_or(&message),
+        None => &message,
+    };
+    report_error(format_args!("{}: {}", file_name, reason), no_messages);
+}
+
+// The settings for the search itself, leaving out those for how its results
+// are printed. `-C` sets both sides of the context, and `-A` or `-B` given
+// as well override it for their side, as in GNU grep.
+fn search_config(args: &Grep) -> SearchConfig {
+    let context = args.context.unwrap_or(0);
+    let after_context = args.after_context.unwrap_or(context);
+    let before_context = args.before_context.unwrap_or(context);
+    SearchConfig {
+        pattern: args
+            .regexp
+            .first()
+            .or(args.pattern.as_ref())
+            .cloned()
+            .unwrap_or_default(),
+        extra_patterns: args.regexp.iter().skip(1).cloned().collect(),
+        fixed_strings: args.fixed_strings,
+        ignore_case: args.ignore_case,
+        case_sensitive: args.case_sensitive,
+        smart_case: args.smart_case,
+        multiline: args.multiline,
+        multiline_dotall: args.multiline_dotall,
+        unicode: !args.no_unicode,
+        pcre2: args.pcre2 || args.engine == RegexEngine::Pcre2,
+        regex_size_limit: args.regex_size_limit.map(size_limit),
+        dfa_size_limit: args.dfa_size_limit.map(size_limit),
+        invert_match: args.invert_match,
+        max_count: args.max_count,
+        count: args.count,
+        count_matches: args.count_matches,
+        total: args.total,
+        stats: args.stats,
+        passthru: args.passthru,
+        only_unmatched: prints_unmatched_parts(args),
+        null_data: args.null_data,
+        quiet: args.quiet,
+        files_with_matches: args.files_with_matches,
+        after_context,
+        before_context,
+        decompress: args.decompress,
+        zip: args.zip,
+        mmap: args.mmap,
+        no_mmap: args.no_mmap,
+        mmap_threshold: args.mmap_threshold,
+    }
+}
+
+// A size limit for the regex crate, which can't go past what fits in memory
+fn size_limit(bytes: u64) -> usize {
+    usize::try_from(bytes).unwrap_or(usize::MAX)
+}
+
+// Orders files for `--sort`, or with `reverse` for `--sortr`
+fn sort_files(files: &mut [String], key: SortKey, reverse: bool, no_messages: bool) {
+    match key {
+        SortKey::None => {}
+        SortKey::Path => sort_files_by(files, reverse, no_messages, |file| Ok(file.to_string())),
+        SortKey::Modified => sort_files_by(files, reverse, no_messages, |file| {
+            fs::metadata(file)?.modified()
+        }),
+        SortKey::Created => sort_files_by(files, reverse, no_messages, |file| {
+            fs::metadata(file)?.created()
+        }),
+        SortKey::Size => sort_files_by(files, reverse, no_messages, |file| {
+            Ok(fs::metadata(file)?.len())
+        }),
+    }
+}
+
+// Sorts files by `key`, keeping files that compare equal in order. Files
+// whose key can't be read go last whichever way the rest are sorted, with a
+// note saying so.
+fn sort_files_by<K: Ord>(
+    files: &mut [String],
+    reverse: bool,
+    no_messages: bool,
+    key: impl Fn(&str) -> io::Result<K>,
+) {
+    let mut keyed: Vec<(Option<K>, String)> = files
+        .iter_mut()
+        .map(|file| {
+            let sort_key = match key(file) {
+                Ok(sort_key) => Some(sort_key),
+                Err(e) => {
+                    if !no_messages {
+                        eprintln!("grep-lite: {}: sorting it last: {}", file, e);
+                    }
+                    None
+                }
+            };
+            (sort_key, std::mem::take(file))
+        })
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) if reverse => b.cmp(a),
+        (Some(a), Some(b)) => a.cmp(b),
+        (a, b) => a.is_none().cmp(&b.is_none()),
+    });
+    for (file, (_, sorted)) in files.iter_mut().zip(keyed) {
+        *file = sorted;
+    }
+}
+
+// `-o -v` prints what's between the matches on every line, so lines that
+// match have to be reported too. They come as context, leaving `-v` to
+// decide what counts.
+fn prints_unmatched_parts(args: &Grep) -> bool {
+    args.only_matching && args.invert_match && output_format(args) == OutputFormat::Text
+}
+
+// `--json` is short for `--output-format json`
+fn output_format(args: &Grep) -> OutputFormat {
+    if args.json {
+        OutputFormat::Json
+    } else {
+        args.output_format
+    }
+}
+
+// Decides whether file names are printed, from `-H`/`-h` or else from
+// whether more than one file is being searched, and whether they go in
+// headings
+fn output_style<'a>(
+    args: &'a Grep,
+    search: &'a CompiledSearch,
+    colors: &'a ColorConfig,
+    template: Option<&'a [FormatPart]>,
+    is_multiple_files: bool,
+) -> OutputStyle<'a> {
+    let file_name_prefix = if args.with_filename {
+        FileNamePrefix::Shown
+    } else if args.no_filename {
+        FileNamePrefix::Suppressed
+    } else if is_multiple_files {
+        FileNamePrefix::Shown
+    } else {
+        FileNamePrefix::Hidden
+    };
+    let to_terminal = args.output.is_none() && io::stdout().is_terminal();
+    let heading = args.heading || (!args.no_heading && to_terminal);
+    let color = match args.color {
+        ColorMode::Auto => to_terminal && env::var_os("NO_COLOR").is_none(),
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+    };
+    OutputStyle {
+        file_name_prefix,
+        heading,
+        null: args.null,
+        format: output_format(args),
+        no_messages: args.no_messages,
+        regex: search.regex(),
+        search,
+        invert_match: args.invert_match,
+        replacement: args.replace.as_deref(),
+        line_number: args.line_number,
+        column: args.column,
+        column_chars: args.column_chars,
+        byte_offset: args.byte_offset,
+        max_columns: args.max_columns.or(args.max_line_length),
+        max_columns_preview: args.max_columns_preview || args.max_line_length.is_some(),
+        trim: args.trim,
+        dedup: args.dedup,
+        initial_tab: args.initial_tab,
+        only_matching: args.only_matching,
+        template,
+        only_matching_group: args
+            .only_matching_group
+            .as_deref()
+            .and_then(|group| capture_group(search.regex(), group))
+            .unwrap_or(0),
+        sort_uniq: args.sort_uniq,
+        null_data: args.null_data,
+        color,
+        colors,
+        label: &args.label,
+        group_separator: (search.config().after_context > 0 || search.config().before_context > 0)
+            && !search.config().passthru,
+    }
+}
+
+// Files bigger than this get a warning with `--in-place`, which reads them
+// whole and builds the edited copy alongside
+const IN_PLACE_WARNING_SIZE: u64 = 256 * 1024 * 1024;
+
+// Applies `--replace` to each file in place, or with `--dry-run` only says
+// what would change. Files that can't be edited are reported and skipped,
+// and give exit status 2.
+fn edit_files_in_place(
+    files: &[String],
+    search: &CompiledSearch,
+    replacement: &str,
+    backup_suffix: Option<&str>,
+    dry_run: bool,
+    no_messages: bool,
+) -> ExitCode {
+    let mut matched = false;
+    for file_name in files {
+        if !no_messages {
+            if let Ok(metadata) = fs::metadata(file_name) {
+                if metadata.len() > IN_PLACE_WARNING_SIZE {
+                    eprintln!(
+                        "grep-lite: {}: editing a {} MiB file in place, which holds it in memory twice",
+                        file_name,
+                        metadata.len() >> 20
+                    );
+                }
+            }
+        }
+        let result = if dry_run {
+            count_replaced_lines(file_name, search, replacement).inspect(|&changed| {
+                if changed > 0 {
+                    eprintln!("Would modify {} lines in {}", changed, file_name);
+                }
+            })
+        } else {
+            replace_in_file(file_name, search, replacement, backup_suffix)
+        };
+        match result {
+            Ok(count) => matched |= count > 0,
+            Err(e) => report_file_error(file_name, &e, no_messages),
+        }
+    }
+    exit_code(matched, false)
+}
+
+// Prints a diff of what `--replace` would change in each file, for `--diff`.
+// Files that wouldn't change are left out, and the exit status is as if the
+// files that would change had matched.
+fn print_replacement_diffs(
+    out: &mut dyn Write,
+    files: &[String],
+    search: &CompiledSearch,
+    replacement: &str,
+    style: &OutputStyle,
+) -> Result<ExitCode> {
+    let mut changed = false;
+    for file_name in files {
+        match replaced_file_contents(file_name, search, replacement) {
+            Ok((original, edited)) if original != edited => {
+                print_diff(
+                    out,
+                    file_name,
+                    &String::from_utf8_lossy(&original),
+                    &String::from_utf8_lossy(&edited),
+                    style,
+                )?;
+                changed = true;
+            }
+            Ok(_) => {}
+            Err(e) => report_file_error(file_name, &e, style.no_messages),
+        }
+    }
+    Ok(exit_code(changed, false))
+}
+
+// Prints a unified diff from `original` to `edited`, naming `file_name` as
+// both sides, with removed lines in red and added ones in green
+fn print_diff(
+    out: &mut dyn Write,
+    file_name: &str,
+    original: &str,
+    edited: &str,
+    style: &OutputStyle,
+) -> Result<()> {
+    writeln!(out, "--- {}", file_name)?;
+    writeln!(out, "+++ {}", file_name)?;
+    let diff = TextDiff::from_lines(original, edited);
+    for hunk in diff.unified_diff().iter_hunks() {
+        writeln!(
+            out,
+            "{}",
+            style.paint(&hunk.header().to_string(), &style.colors.separator)
+        )?;
+        for change in hunk.iter_changes() {
+            let (sign, color) = match change.tag() {
+                ChangeTag::Delete => ("-", "31"),
+                ChangeTag::Insert => ("+", "32"),
+                ChangeTag::Equal => (" ", ""),
+            };
+            let line = change.value();
+            let line = line.strip_suffix('\n').unwrap_or(line);
+            writeln!(out, "{}", style.paint(&format!("{}{}", sign, line), color))?;
+            if change.missing_newline() {
+                writeln!(out, "\\ No newline at end of file")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Process a single file, adding what was found to `summary`. A file that
+// can't be opened is reported and left out.
+fn process_file(
+    file_name: &str,
+    search: &CompiledSearch,
+    style: OutputStyle,
+    summary: &mut Summary,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let mut sink = StdoutSink::new(style.input_name(file_name), style, out);
+    match search_input_with_sink(file_name, search, &mut sink) {
+        Ok(count) => {
+            sink.finish()?;
+            summary.add(count, sink);
+        }
+        Err(e) => report_file_error(style.input_name(file_name), &e, style.no_messages),
+    }
+    Ok(())
+}
+
+// Searches the file at `file_name`, or standard input for `-`. Standard
+// input given twice is read to the end the first time, so is empty after.
+fn search_input_with_sink(
+    file_name: &str,
+    search: &CompiledSearch,
+    sink: &mut dyn Sink,
+) -> Result<usize> {
+    if file_name == STDIN_NAME {
+        search_reader_with_sink(io::stdin().lock(), search, sink)
+    } else {
+        search_file_with_sink(file_name, search, sink)
+    }
+}
+
+// A file's buffered events and count, as sent from a worker to the printer
+type FileResult = Result<(Vec<MatchEvent>, usize)>;
+
+// Searches files on a thread pool, adding what was found to `summary`. Each
+// file's output is buffered and printed in the order the files were given, so
+// the result matches a serial search.
+fn search_files_in_parallel(
+    files: &[String],
+    search: &CompiledSearch,
+    threads: Option<usize>,
+    style: OutputStyle,
+    summary: &mut Summary,
+    out: &mut (dyn Write + Send),
+) -> Result<()> {
+    // A pool buys nothing for a single file, so keep that on the serial path.
+    // `--quiet` stays serial too, as it stops at the first match.
+    let quiet = search.config().quiet;
+    if files.len() < 2 || threads == Some(1) || quiet {
+        for file_name in files {
+            if quiet && summary.count > 0 {
+                break;
+            }
+            process_file(file_name, search, style, summary, out)?;
+        }
+        return Ok(());
+    }
+
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        let printer = scope.spawn(|| print_in_order(receiver, files, style, summary, out));
+
+        files
+            .par_iter()
+            .enumerate()
+            .try_for_each_with(sender, |sender, (position, file_name)| {
+                let mut sink = VecSink::default();
+                let result = search_input_with_sink(file_name, search, &mut sink)
+                    .map(|count| (sink.events, count));
+                sender.send((position, result))
+            })
+            // The printer only hangs up once it has failed writing, so the
+            // files left aren't searched, and its error is returned below
+            .ok();
+
+        match printer.join() {
+            Ok(printed) => printed,
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    })
+}
+
+// Prints each file's buffered events once every file before it has been
+// printed, and adds them to `summary`
+fn print_in_order(
+    results: Receiver<(usize, FileResult)>,
+    files: &[String],
+    style: OutputStyle,
+    summary: &mut Summary,
+    out: &mut (dyn Write + Send),
+) -> Result<()> {
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+
+    for (position, result) in results {
+        pending.insert(position, result);
+        while let Some(result) = pending.remove(&next) {
+            let mut sink = StdoutSink::new(style.input_name(&files[next]), style, out);
+            match result {
+                Ok((events, count)) => {
+                    for event in events {
+                        sink.emit(event);
+                    }
+                    sink.finish()?;
+                    summary.add(count, sink);
+                }
+                Err(e) => report_file_error(style.input_name(&files[next]), &e, style.no_messages),
+            }
+            next += 1;
+        }
+    }
+    Ok(())
+}
+
+fn print_line_with_highlighted_text(
+    out: &mut dyn Write,
+    line: &str,
+    index: usize,
+    offset: u64,
+    is_context: bool,
+    file_name: Option<&str>,
+    style: &OutputStyle,
+) -> Result<()> {
+    let colors = style.colors;
+    let shown = &line[trimmed_len(line, style)..];
+    let highlighted_line = if is_omitted(shown, style) {
+        format!(
+            "[Omitted long line with {} matches]",
+            style.regex.find_iter(line).count()
+        )
+    } else if style.invert_match {
+        let line = truncate_line(shown, preview_columns(style));
+        if is_context {
+            style.paint(&line, &colors.context)
+        } else {
+            line
+        }
+    } else {
+        highlight_matches(line, is_context, style)
+    };
+
+    let separator = style.paint(":", &colors.separator);
+    let terminator = if style.null_data { '\0' } else { '\n' };
+    // Prints the file name and numbers before the text of the line, with the
+    // column of what starts `start` bytes into it
+    let print_prefix = |out: &mut dyn Write, start: usize| -> Result<()> {
+        if let Some(file_name) = file_name {
+            print_file_name(
+                out,
+                &style.paint(file_name, &colors.file_name),
+                &separator,
+                style.null,
+            )?;
+        }
+        // The numbers printed before the line, each followed by a separator
+        let mut prefix = String::new();
+        if style.line_number {
+            let line_number_color = if is_context {
+                &colors.context_line_number
+            } else {
+                &colors.line_number
+            };
+            let line_number = style.paint(&(index + 1).to_string(), line_number_color);
+            prefix.push_str(&format!("{}{}", line_number, separator));
+        }
+        if style.column {
+            let column = if style.column_chars {
+                line[..start].chars().count() + 1
+            } else {
+                start + 1
+            };
+            prefix.push_str(&format!("{}{}", column, separator));
+        }
+        if style.byte_offset {
+            prefix.push_str(&format!("{}{}", offset + start as u64, separator));
+        }
+        if style.initial_tab && (file_name.is_some() || !prefix.is_empty()) {
+            write!(out, "{}\t", prefix)?;
+        } else if !prefix.is_empty() {
+            write!(out, "{} ", prefix)?;
+        }
+        Ok(())
+    };
+
+    if style.only_matching {
+        // Each match is printed as a line of its own, numbered as the line
+        // it is on. With `-v` the parts between them aren't coloured.
+        for (start, text) in matched_texts(line, style) {
+            print_prefix(out, start)?;
+            let color = if style.invert_match {
+                ""
+            } else {
+                match_color(line, start, is_context, style)
+            };
+            write!(out, "{}{}", style.paint(&text, color), terminator)?;
+        }
+        return Ok(());
+    }
+
+    // The column is of the first match in the line as read, before any
+    // highlighting or replacing. Lines with no match to point at get 1, and
+    // the byte offset is of the line itself.
+    if style.column && !style.invert_match {
+        print_prefix(out, style.regex.find(line).map_or(0, |found| found.start))?;
+    } else {
+        print_prefix(out, 0)?;
+    }
+    write!(out, "{}{}", highlighted_line, terminator)
+}
+
+// Where each match in `line` starts, and its text for `-o`, which is what
+// replaces it with `--replace`, or else its `--only-matching-group`. With
+// `-v` these are the non-empty parts of the line between the matches.
+fn matched_texts(line: &str, style: &OutputStyle) -> Vec<(usize, String)> {
+    if style.invert_match {
+        let mut parts = Vec::new();
+        let mut last = 0;
+        for found in style.regex.find_iter(line) {
+            if found.start > last {
+                parts.push((last, line[last..found.start].to_string()));
+            }
+            last = found.end;
+        }
+        if last < line.len() {
+            parts.push((last, line[last..].to_string()));
+        }
+        return parts;
+    }
+    // PCRE2 can't be used with `--replace` or `--only-matching-group`, so
+    // its matches are printed as they are
+    let Some(regex) = style.regex.as_regex() else {
+        return style
+            .regex
+            .find_iter(line)
+            .map(|found| (found.start, line[found].to_string()))
+            .collect();
+    };
+    regex
+        .captures_iter(line)
+        .filter_map(|caps| {
+            let found = caps.get(0).expect("group 0 is the whole match");
+            let text = match style.replacement {
+                Some(replacement) => {
+                    let mut text = String::new();
+                    caps.expand(replacement, &mut text);
+                    text
+                }
+                None => caps.get(style.only_matching_group)?.as_str().to_string(),
+            };
+            Some((found.start(), text))
+        })
+        .collect()
+}
+
+// The index of the capture group `group` names in `regex`, given either as a
+// number or as the group's name
+fn capture_group(regex: &CompiledRegex, group: &str) -> Option<usize> {
+    let regex = regex.as_regex()?;
+    match group.parse::<usize>() {
+        Ok(index) => (index < regex.captures_len()).then_some(index),
+        Err(_) => regex.capture_names().position(|name| name == Some(group)),
+    }
+}
+
+// Prints `template` for each match in `line`, or once with nothing for the
+// match when there are none, as for a line found by `-v`
+fn print_with_template(
+    out: &mut dyn Write,
+    line: &str,
+    index: usize,
+    file_name: &str,
+    template: &[FormatPart],
+    style: &OutputStyle,
+) -> Result<()> {
+    // The span of each match, and of each of its capture groups
+    let matches: Vec<Vec<Option<Range<usize>>>> = match style.regex.as_regex() {
+        Some(regex) => regex
+            .captures_iter(line)
+            .map(|caps| caps.iter().map(|group| group.map(|m| m.range())).collect())
+            .collect(),
+        None => style
+            .regex
+            .find_iter(line)
+            .map(|found| vec![Some(found)])
+            .collect(),
+    };
+    let no_match = [None];
+    let terminator = if style.null_data { '\0' } else { '\n' };
+    let groups = matches.iter().map(Vec::as_slice);
+    for groups in groups.chain(matches.is_empty().then_some(&no_match[..])) {
+        let mut output = String::new();
+        for part in template {
+            match part {
+                FormatPart::Literal(text) => output.push_str(text),
+                FormatPart::File => {
+                    output.push_str(&style.paint(file_name, &style.colors.file_name))
+                }
+                FormatPart::Line => output.push_str(&(index + 1).to_string()),
+                FormatPart::Column => {
+                    if let Some(Some(found)) = groups.first() {
+                        let column = if style.column_chars {
+                            line[..found.start].chars().count() + 1
+                        } else {
+                            found.start + 1
+                        };
+                        output.push_str(&column.to_string());
+                    }
+                }
+                FormatPart::Match => {
+                    if let Some(Some(found)) = groups.first() {
+                        let color = match_color(line, found.start, false, style);
+                        output.push_str(&style.paint(&line[found.clone()], color));
+                    }
+                }
+                FormatPart::Text => output.push_str(line),
+                FormatPart::Group(group) => {
+                    if let Some(Some(found)) = groups.get(*group) {
+                        output.push_str(&line[found.clone()]);
+                    }
+                }
+            }
+        }
+        write!(out, "{}{}", output, terminator)?;
+    }
+    Ok(())
+}
+
+// Colours each match in `line`, or the text replacing it with `--replace`,
+// and the rest of it too in a context line, then trims it for `--trim` and
+// cuts it short at `--max-columns-preview`. The cut is made in the text
+// before colouring, so it never splits an escape sequence, and a match
+// running past it is coloured up to the cut.
+fn highlight_matches(line: &str, is_context: bool, style: &OutputStyle) -> String {
+    // The line as it will be printed, and where each match ends up in it,
+    // along with the colour for the pattern it matched
+    let mut text = String::with_capacity(line.len());
+    let mut matches = Vec::new();
+    let mut last = 0;
+    match (style.replacement, style.regex.as_regex()) {
+        (Some(replacement), Some(regex)) => {
+            for caps in regex.captures_iter(line) {
+                let found = caps.get(0).expect("group 0 is the whole match");
+                text.push_str(&line[last..found.start()]);
+                let start = text.len();
+                caps.expand(replacement, &mut text);
+                matches.push((
+                    start..text.len(),
+                    match_color(line, found.start(), is_context, style),
+                ));
+                last = found.end();
+            }
+        }
+        _ => {
+            for found in style.regex.find_iter(line) {
+                text.push_str(&line[last..found.start]);
+                let color = match_color(line, found.start, is_context, style);
+                matches.push((text.len()..text.len() + found.len(), color));
+                text.push_str(&line[found.clone()]);
+                last = found.end;
+            }
+        }
+    }
+    text.push_str(&line[last..]);
+
+    let rest_color = if is_context {
+        style.colors.context.as_str()
+    } else {
+        ""
+    };
+    // Matches are found in the whole line, so any in the trimmed whitespace
+    // are dropped
+    let trimmed = trimmed_len(&text, style);
+    let cut = trimmed + truncation_point(&text[trimmed..], preview_columns(style));
+    let mut highlighted = String::with_capacity(cut - trimmed);
+    let mut last = trimmed;
+    for (found, match_color) in matches {
+        let found = found.start.max(trimmed)..found.end.max(trimmed);
+        if found.start > cut || (found.start == cut && !found.is_empty()) {
+            break;
+        }
+        let end = found.end.min(cut);
+        highlighted.push_str(&style.paint(&text[last..found.start], rest_color));
+        highlighted.push_str(&style.paint(&text[found.start..end], match_color));
+        last = end;
+    }
+    highlighted.push_str(&style.paint(&text[last..cut], rest_color));
+    if cut < text.len() {
+        highlighted.push_str(TRUNCATED);
+    }
+    highlighted
+}
+
+// How much whitespace to leave out at the start of `line`, for `--trim`
+fn trimmed_len(line: &str, style: &OutputStyle) -> usize {
+    if style.trim {
+        line.len() - line.trim_start_matches([' ', '\t']).len()
+    } else {
+        0
+    }
+}
+
+// The colours given to each pattern's matches when there's more than one
+// pattern: red, blue, yellow, green, magenta and cyan, then round again
+const PATTERN_COLORS: [&str; 6] = ["1;31", "1;34", "1;33", "1;32", "1;35", "1;36"];
+
+// The colour of the match starting at `start` in `line`. With one pattern it
+// comes from `GREP_COLORS`, and with several from the pattern it matched.
+fn match_color<'a>(line: &str, start: usize, is_context: bool, style: &OutputStyle<'a>) -> &'a str {
+    if style.search.regexes().len() > 1 {
+        let index = style.search.pattern_index(line, start).unwrap_or(0);
+        PATTERN_COLORS[index % PATTERN_COLORS.len()]
+    } else if is_context {
+        &style.colors.context_match
+    } else {
+        &style.colors.selected_match
+    }
+}
+
+// Whether `line` is too long to print, for `--max-columns` without
+// `--max-columns-preview`
+fn is_omitted(line: &str, style: &OutputStyle) -> bool {
+    match style.max_columns {
+        Some(max) => !style.max_columns_preview && line.len() > max,
+        None => false,
+    }
+}
+
+// Where long lines are cut short, for `--max-columns-preview`
+fn preview_columns(style: &OutputStyle) -> Option<usize> {
+    style.max_columns.filter(|_| style.max_columns_preview)
+}
+
+// Follows a line cut short by `--max-columns-preview`
+const TRUNCATED: &str = " [... truncated]";
+
+// Cuts `line` short at `--max-columns-preview`, marking where it was cut
+fn truncate_line(line: &str, max_columns: Option<usize>) -> String {
+    let end = truncation_point(line, max_columns);
+    if end < line.len() {
+        format!("{}{}", &line[..end], TRUNCATED)
+    } else {
+        line.to_string()
+    }
+}
+
+// Where to cut `line` for `--max-columns-preview`: at most that many bytes
+// in, moved back to the start of any character it would split
+fn truncation_point(line: &str, max_columns: Option<usize>) -> usize {
+    match max_columns {
+        Some(max) if line.len() > max => {
+            let mut end = max;
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            end
+        }
+        _ => line.len(),
+    }
+}
+
+// The input that stands for standard input
+const STDIN_NAME: &str = "-";
+
+// The first line of `--output-format csv`. Match offsets are 0-based bytes
+// into the line, and are left empty for context and for `-v`.
+const CSV_HEADER: &str = "file,line_number,match_start,match_end,full_line,context";
+
+fn print_csv_row(
+    out: &mut dyn Write,
+    file_name: &str,
+    index: usize,
+    found: Option<Range<usize>>,
+    line: &str,
+    is_context: bool,
+) -> Result<()> {
+    let (start, end) = match found {
+        Some(found) => (found.start.to_string(), found.end.to_string()),
+        None => (String::new(), String::new()),
+    };
+    writeln!(
+        out,
+        "{},{},{},{},{},{}",
+        csv_field(file_name),
+        index + 1,
+        start,
+        end,
+        csv_field(line),
+        is_context
+    )
+}
+
+// Quotes a CSV field if it holds anything that would break up the row, with
+// any quotes inside it doubled
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+fn print_json(out: &mut dyn Write, message: &json::Message) -> Result<()> {
+    serde_json::to_writer(&mut *out, message)?;
+    out.write_all(b"\n")
+}
+
+// Prints a file name followed by `separator`, or by a NUL byte with `--null`.
+// The name is written as raw bytes so nothing in it gets reformatted.
+fn print_file_name(
+    out: &mut dyn Write,
+    file_name: &str,
+    separator: &str,
+    null: bool,
+) -> Result<()> {
+    let separator = if null { "\0" } else { separator };
+    out.write_all(file_name.as_bytes())?;
+    out.write_all(separator.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The context `search_config` gives for these options, as (after, before)
+    fn context(options: &[&str]) -> (usize, usize) {
+        let args = Grep::try_parse_from(
+            ["grep-lite"]
+                .iter()
+                .chain(options)
+                .chain(&["pattern", "file"]),
+        )
+        .unwrap();
+        let config = search_config(&args);
+        (config.after_context, config.before_context)
+    }
+
+    #[test]
+    fn context_options_take_precedence_over_context() {
+        assert_eq!(context(&[]), (0, 0));
+        assert_eq!(context(&["-C", "2"]), (2, 2));
+        assert_eq!(context(&["-A", "1"]), (1, 0));
+        assert_eq!(context(&["-B", "3"]), (0, 3));
+        assert_eq!(context(&["-A", "1", "-B", "3"]), (1, 3));
+        // `-A` and `-B` win over `-C`, whichever comes first
+        assert_eq!(context(&["-C", "2", "-A", "5"]), (5, 2));
+        assert_eq!(context(&["-A", "5", "-C", "2"]), (5, 2));
+        assert_eq!(context(&["-C", "2", "-B", "0"]), (2, 0));
+        assert_eq!(context(&["-B", "0", "-C", "2"]), (2, 0));
+        assert_eq!(context(&["-A", "1", "-C", "2", "-B", "3"]), (1, 3));
+        // Given twice, the last one counts
+        assert_eq!(context(&["-C", "1", "-C", "4"]), (4, 4));
+        assert_eq!(context(&["-A", "1", "-A", "4", "-C", "2"]), (4, 2));
+    }
+
+    #[test]
+    fn context_values_must_be_numbers_of_lines() {
+        assert_eq!(parse_context("0"), Ok(0));
+        assert_eq!(parse_context("12"), Ok(12));
+        for value in ["-1", "abc", "", "1.5", " 2"] {
+            assert_eq!(
+                parse_context(value),
+                Err(format!(
+                    "invalid context value '{}', expected a number of lines",
+                    value
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_context_values_are_reported_by_clap() {
+        let Err(error) = Grep::try_parse_from(["grep-lite", "-A", "abc", "pattern"]) else {
+            panic!("expected -A abc to be refused");
+        };
+
+        assert!(error
+            .to_string()
+            .contains("invalid context value 'abc', expected a number of lines"));
+    }
+
+    #[test]
+    fn file_sizes_take_suffixes() {
+        assert_eq!(parse_file_size("0"), Ok(0));
+        assert_eq!(parse_file_size("512"), Ok(512));
+        assert_eq!(parse_file_size("500K"), Ok(500 * 1024));
+        assert_eq!(parse_file_size("500k"), Ok(500 * 1024));
+        assert_eq!(parse_file_size("10M"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_file_size("1g"), Ok(1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn file_sizes_reject_anything_else() {
+        for value in [
+            "10X",
+            "",
+            "K",
+            "-1",
+            "1.5M",
+            "10 M",
+            "10KB",
+            "99999999999999999999G",
+        ] {
+            assert_eq!(
+                parse_file_size(value),
+                Err(format!(
+                    "invalid size '{}', expected a number like 500K, 10M or 1G",
+                    value
+                )),
+            );
+        }
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("plain text"), "plain text");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("ends\r"), "\"ends\r\"");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn csv_rows_quote_file_names_and_lines() {
+        let mut out = Vec::new();
+        print_csv_row(
+            &mut out,
+            "a,\"b\".txt",
+            0,
+            Some(5..7),
+            "say \"hi\", bob",
+            false,
+        )
+        .unwrap();
+        print_csv_row(&mut out, "c.txt", 2, None, "two\nlines", true).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\"a,\"\"b\"\".txt\",1,5,7,\"say \"\"hi\"\", bob\",false\n\
+             c.txt,3,,,\"two\nlines\",true\n"
+        );
+    }
+
+    // Whether the filter for these `--glob` values lets `path` through
+    fn globbed(globs: &[&str], path: &str) -> bool {
+        let globs: Vec<String> = globs.iter().map(|glob| glob.to_string()).collect();
+        GlobFilter::new(&globs).is_match(Path::new(path))
+    }
+
+    #[test]
+    fn globs_without_includes_let_everything_else_through() {
+        assert!(globbed(&[], "src/main.rs"));
+        assert!(globbed(&["!*.txt"], "src/main.rs"));
+        assert!(!globbed(&["!*.txt"], "notes.txt"));
+    }
+
+    #[test]
+    fn globs_with_includes_only_let_those_through() {
+        assert!(globbed(&["*.rs"], "src/main.rs"));
+        assert!(globbed(&["*.rs"], "./src/main.rs"));
+        assert!(!globbed(&["*.rs"], "notes.txt"));
+        assert!(!globbed(&["*.rs", "!*.txt"], "notes.txt"));
+        assert!(!globbed(&["*.rs", "!*.txt"], "Makefile"));
+    }
+
+    #[test]
+    fn the_last_matching_glob_wins() {
+        let globs = ["*.rs", "!tests/**", "tests/common/*"];
+
+        assert!(globbed(&globs, "src/main.rs"));
+        assert!(!globbed(&globs, "tests/context.rs"));
+        assert!(globbed(&globs, "tests/common/mod.rs"));
+        assert!(!globbed(&globs, "tests/fixtures/lines.txt"));
+        // An exclude given first is overridden by any include after it
+        assert!(globbed(&["!*.rs", "*"], "src/main.rs"));
+        assert!(!globbed(&["*", "!*.rs"], "src/main.rs"));
+    }
 }
@@ -0,0 +1,53 @@
+mod common;
+
+use common::{fixtures, grep_lite, stdout, temp_dir};
+use std::fs;
+
+// `arc.zip` holds `src/a.rs`, `b.py` and `c.txt`, each with a line of "needle"
+
+#[test]
+fn every_file_in_an_archive_is_searched() {
+    let output = grep_lite(&fixtures(), &["needle", "arc.zip"]);
+
+    assert_eq!(
+        stdout(&output),
+        "arc.zip!/src/a.rs:needle in rust\narc.zip!/b.py:needle in python\n\
+         arc.zip!/c.txt:needle in text\n"
+    );
+}
+
+#[test]
+fn globs_pick_out_files_in_an_archive() {
+    let output = grep_lite(&fixtures(), &["-g", "*.rs", "needle", "arc.zip"]);
+    assert_eq!(stdout(&output), "arc.zip!/src/a.rs:needle in rust\n");
+
+    let output = grep_lite(&fixtures(), &["-g", "!*.txt", "needle", "arc.zip"]);
+    assert_eq!(
+        stdout(&output),
+        "arc.zip!/src/a.rs:needle in rust\narc.zip!/b.py:needle in python\n"
+    );
+}
+
+#[test]
+fn types_pick_out_files_in_an_archive() {
+    let output = grep_lite(&fixtures(), &["--type", "py", "needle", "arc.zip"]);
+    assert_eq!(stdout(&output), "arc.zip!/b.py:needle in python\n");
+
+    let output = grep_lite(&fixtures(), &["--type-not", "py", "needle", "arc.zip"]);
+    assert_eq!(
+        stdout(&output),
+        "arc.zip!/src/a.rs:needle in rust\narc.zip!/c.txt:needle in text\n"
+    );
+}
+
+#[test]
+fn recursive_search_filters_files_in_archives() {
+    let dir = temp_dir("recursive_search_filters_files_in_archives");
+    fs::create_dir(dir.join("z")).unwrap();
+    fs::copy(fixtures().join("arc.zip"), dir.join("z/arc.zip")).unwrap();
+
+    let output = grep_lite(&dir, &["-r", "-g", "!*.py", "-g", "!*.txt", "needle"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "z/arc.zip!/src/a.rs:needle in rust\n");
+}
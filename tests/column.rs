@@ -0,0 +1,48 @@
+mod common;
+
+use common::{grep_lite, stdout, temp_dir};
+use std::fs;
+
+const LINES: &str = "héllo wörld world\nplain world\n日本 world\n";
+
+#[test]
+fn column_counts_bytes_before_the_match() {
+    let dir = temp_dir("column_counts_bytes");
+    fs::write(dir.join("a.txt"), LINES).unwrap();
+
+    let output = grep_lite(&dir, &["--column", "world", "a.txt"]);
+
+    assert_eq!(
+        stdout(&output),
+        "15: héllo wörld world\n7: plain world\n8: 日本 world\n"
+    );
+}
+
+#[test]
+fn column_chars_counts_characters_before_the_match() {
+    let dir = temp_dir("column_chars_counts_characters");
+    fs::write(dir.join("a.txt"), LINES).unwrap();
+
+    let output = grep_lite(&dir, &["--column", "--column-chars", "world", "a.txt"]);
+
+    assert_eq!(
+        stdout(&output),
+        "13: héllo wörld world\n7: plain world\n4: 日本 world\n"
+    );
+}
+
+#[test]
+fn column_chars_gives_each_match_its_own_column() {
+    let dir = temp_dir("column_chars_each_match");
+    fs::write(dir.join("a.txt"), LINES).unwrap();
+
+    let output = grep_lite(
+        &dir,
+        &["-n", "-o", "--column", "--column-chars", "w.rld", "a.txt"],
+    );
+
+    assert_eq!(
+        stdout(&output),
+        "1:7: wörld\n1:13: world\n2:7: world\n3:4: world\n"
+    );
+}
@@ -0,0 +1,70 @@
+// Helpers shared by the integration tests, which run the built binary
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+// An empty directory of its own for a test, under Cargo's scratch space
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// The directory holding the checked-in fixtures
+pub fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+// Runs grep-lite in `dir` with `args`, ignoring any `GREP_LITE_OPTIONS` the
+// environment running the tests has set
+pub fn grep_lite(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_grep-lite-2"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("GREP_LITE_OPTIONS")
+        .env_remove("GREP_COLORS")
+        .output()
+        .unwrap()
+}
+
+// Runs grep-lite in `dir` with `args` and the environment variables in `vars`
+pub fn grep_lite_with_env(dir: &Path, args: &[&str], vars: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_grep-lite-2"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("GREP_LITE_OPTIONS")
+        .env_remove("GREP_COLORS")
+        .envs(vars.iter().copied())
+        .output()
+        .unwrap()
+}
+
+// Runs grep-lite in `dir` with `args`, giving it `stdin` as its standard input
+pub fn grep_lite_with_stdin(dir: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_grep-lite-2"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("GREP_LITE_OPTIONS")
+        .env_remove("GREP_COLORS")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
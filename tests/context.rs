@@ -0,0 +1,108 @@
+mod common;
+
+use common::{grep_lite, grep_lite_with_env, stdout, temp_dir};
+use std::{fs, path::PathBuf};
+
+const LINES: &str = "one\ntwo\nmatch a\nthree\nfour\nfive\nsix\nmatch b\nmatch c\nseven\n";
+
+fn lines_file(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::write(dir.join("lines.txt"), LINES).unwrap();
+    dir
+}
+
+// Output with context before matches, with `--` between groups of lines
+// that aren't next to each other
+#[test]
+fn before_context_output_is_unchanged() {
+    let dir = lines_file("before_context_output_is_unchanged");
+
+    let output = grep_lite(&dir, &["-n", "-B", "2", "match", "lines.txt"]);
+    assert_eq!(
+        output.stdout,
+        b"1: one\n2: two\n3: match a\n--\n6: five\n7: six\n8: match b\n9: match c\n"
+    );
+
+    let output = grep_lite(&dir, &["-n", "-B", "1", "match", "lines.txt"]);
+    assert_eq!(
+        output.stdout,
+        b"2: two\n3: match a\n--\n7: six\n8: match b\n9: match c\n"
+    );
+
+    let output = grep_lite(&dir, &["-n", "-B", "3", "-A", "1", "match", "lines.txt"]);
+    assert_eq!(
+        output.stdout,
+        b"1: one\n2: two\n3: match a\n4: three\n5: four\n6: five\n7: six\n8: match b\n\
+          9: match c\n10: seven\n"
+    );
+}
+
+#[test]
+fn no_before_context_is_the_same_as_none_asked_for() {
+    let dir = lines_file("no_before_context");
+
+    let without = grep_lite(&dir, &["-n", "match", "lines.txt"]);
+    let zero = grep_lite(&dir, &["-n", "-B", "0", "match", "lines.txt"]);
+
+    assert_eq!(stdout(&without), "3: match a\n8: match b\n9: match c\n");
+    assert_eq!(zero.stdout, without.stdout);
+}
+
+#[test]
+fn distant_matches_have_their_context_groups_separated() {
+    let dir = lines_file("distant_matches_have_their_context_groups_separated");
+    fs::copy(dir.join("lines.txt"), dir.join("more.txt")).unwrap();
+
+    let output = grep_lite(&dir, &["-n", "-C1", "match", "lines.txt"]);
+    assert_eq!(
+        stdout(&output),
+        "2: two\n3: match a\n4: three\n--\n7: six\n8: match b\n9: match c\n10: seven\n"
+    );
+
+    // Groups from different files are set apart too
+    let output = grep_lite(&dir, &["-C1", "match a", "lines.txt", "more.txt"]);
+    assert_eq!(
+        stdout(&output),
+        "lines.txt:two\nlines.txt:match a\nlines.txt:three\n--\n\
+         more.txt:two\nmore.txt:match a\nmore.txt:three\n"
+    );
+
+    // Without context, there are no groups to separate
+    let output = grep_lite(&dir, &["-n", "match", "lines.txt"]);
+    assert_eq!(stdout(&output), "3: match a\n8: match b\n9: match c\n");
+}
+
+#[test]
+fn context_groups_are_separated_under_headings() {
+    let dir = lines_file("context_groups_are_separated_under_headings");
+    fs::copy(dir.join("lines.txt"), dir.join("more.txt")).unwrap();
+
+    let output = grep_lite(
+        &dir,
+        &["--heading", "-C1", "match", "lines.txt", "more.txt"],
+    );
+
+    // Files are set apart by a blank line instead
+    let group = "two\nmatch a\nthree\n--\nsix\nmatch b\nmatch c\nseven\n";
+    assert_eq!(
+        stdout(&output),
+        format!("lines.txt\n{group}\nmore.txt\n{group}")
+    );
+}
+
+#[test]
+fn group_separators_are_coloured_with_se() {
+    let dir = lines_file("group_separators_are_coloured_with_se");
+    let separator = |vars: &[(&str, &str)]| {
+        let output =
+            grep_lite_with_env(&dir, &["--color=always", "-C1", "match", "lines.txt"], vars);
+        stdout(&output).lines().nth(3).unwrap().to_string()
+    };
+
+    assert_eq!(separator(&[]), "\x1b[36m--\x1b[0m");
+    assert_eq!(
+        separator(&[("GREP_COLORS", "se=01;33")]),
+        "\x1b[01;33m--\x1b[0m"
+    );
+    assert_eq!(separator(&[("GREP_COLORS", "se=")]), "--");
+}
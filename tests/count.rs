@@ -0,0 +1,72 @@
+mod common;
+
+use common::{grep_lite, stdout, temp_dir};
+use std::fs;
+
+#[test]
+fn count_matches_counts_a_pattern_three_times_on_one_line() {
+    let dir = temp_dir("count_matches_three_on_a_line");
+    fs::write(dir.join("a.txt"), "ab ab xab\nnone\n").unwrap();
+
+    let output = grep_lite(&dir, &["--count-matches", "ab", "a.txt"]);
+    assert_eq!(stdout(&output), "3\n");
+
+    let output = grep_lite(&dir, &["-c", "ab", "a.txt"]);
+    assert_eq!(stdout(&output), "1\n");
+}
+
+#[test]
+fn inverted_count_ignores_context() {
+    let dir = temp_dir("inverted_count_ignores_context");
+    fs::write(dir.join("a.txt"), "a\nneedle\nb\nc\nneedle\nd\n").unwrap();
+
+    for context in [&[][..], &["-C", "1"], &["-A", "2"], &["-B", "3"]] {
+        let mut args = vec!["-c", "-v"];
+        args.extend(context);
+        args.extend(["needle", "a.txt"]);
+
+        let output = grep_lite(&dir, &args);
+
+        assert_eq!(stdout(&output), "4\n", "{:?}", context);
+    }
+}
+
+fn three_files(name: &str) -> std::path::PathBuf {
+    let dir = temp_dir(name);
+    fs::write(dir.join("one.txt"), "b\nb\nx\n").unwrap();
+    fs::write(dir.join("none.txt"), "x\n").unwrap();
+    fs::write(dir.join("two.txt"), "b\n").unwrap();
+    dir
+}
+
+#[test]
+fn count_gives_every_file_its_count() {
+    let dir = three_files("count_gives_every_file_its_count");
+
+    let output = grep_lite(&dir, &["-c", "b", "one.txt", "none.txt", "two.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "one.txt: 2\nnone.txt: 0\ntwo.txt: 1\n");
+}
+
+#[test]
+fn files_with_matches_names_only_matching_files() {
+    let dir = three_files("files_with_matches_names_matching_files");
+
+    let output = grep_lite(&dir, &["-l", "b", "one.txt", "none.txt", "two.txt"]);
+    assert_eq!(stdout(&output), "one.txt\ntwo.txt\n");
+
+    let output = grep_lite(&dir, &["-l", "b", "none.txt"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn count_with_files_with_matches_leaves_out_zero_counts() {
+    let dir = three_files("count_with_files_with_matches");
+
+    let output = grep_lite(&dir, &["-c", "-l", "b", "one.txt", "none.txt", "two.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "one.txt: 2\ntwo.txt: 1\n");
+}
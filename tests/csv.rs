@@ -0,0 +1,35 @@
+mod common;
+
+use common::{grep_lite, stdout, temp_dir};
+use std::fs;
+
+#[test]
+fn csv_quotes_file_names_and_lines_with_commas_and_quotes() {
+    let dir = temp_dir("csv_quotes_commas_and_quotes");
+    fs::write(dir.join("a,\"b\".txt"), "say \"hi\", bob\nplain\n").unwrap();
+
+    let output = grep_lite(&dir, &["--output-format", "csv", "hi", "a,\"b\".txt"]);
+
+    assert_eq!(
+        stdout(&output),
+        "file,line_number,match_start,match_end,full_line,context\n\
+         \"a,\"\"b\"\".txt\",1,5,7,\"say \"\"hi\"\", bob\",false\n"
+    );
+}
+
+#[test]
+fn csv_quotes_records_holding_newlines() {
+    let dir = temp_dir("csv_quotes_newlines");
+    fs::write(dir.join("nul.dat"), "x\0two\nlines, \"q\"\0").unwrap();
+
+    let output = grep_lite(
+        &dir,
+        &["--output-format", "csv", "--null-data", "lines", "nul.dat"],
+    );
+
+    assert_eq!(
+        stdout(&output),
+        "file,line_number,match_start,match_end,full_line,context\n\
+         nul.dat,2,4,9,\"two\nlines, \"\"q\"\"\",false\n"
+    );
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use common::{fixtures, grep_lite, stderr, stdout, temp_dir};
+use std::fs;
+
+#[test]
+fn gzip_files_are_searched_decompressed() {
+    let output = grep_lite(&fixtures(), &["beta", "lines.txt.gz", "lines-gzip.dat"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "lines.txt.gz:beta\nlines-gzip.dat:beta\n");
+}
+
+#[test]
+fn decompress_reads_plain_files_as_they_are() {
+    let output = grep_lite(&fixtures(), &["-z", "beta", "lines.txt", "lines.txt.gz"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "lines.txt:beta\nlines.txt.gz:beta\n");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn search_zip_recurses_through_compressed_and_plain_files() {
+    let dir = temp_dir("search_zip_recurses");
+    fs::copy(fixtures().join("lines.txt"), dir.join("plain.txt")).unwrap();
+    fs::copy(fixtures().join("lines.txt.gz"), dir.join("good.txt.gz")).unwrap();
+
+    let output = grep_lite(&dir, &["-r", "--search-zip", "beta"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "good.txt.gz:beta\nplain.txt:beta\n");
+}
+
+#[test]
+fn corrupt_or_truncated_gzip_is_reported_and_the_search_goes_on() {
+    let dir = temp_dir("corrupt_or_truncated_gzip");
+    let gzip = fs::read(fixtures().join("lines.txt.gz")).unwrap();
+    fs::write(dir.join("a-truncated.txt.gz"), &gzip[..20]).unwrap();
+    fs::write(dir.join("b-corrupt.gz"), b"\x1f\x8bnot really gzip").unwrap();
+    fs::copy(fixtures().join("lines.txt.gz"), dir.join("c-good.txt.gz")).unwrap();
+    fs::copy(fixtures().join("lines.txt"), dir.join("d-plain.txt")).unwrap();
+
+    let output = grep_lite(&dir, &["-r", "--search-zip", "beta"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "c-good.txt.gz:beta\nd-plain.txt:beta\n");
+    let errors = stderr(&output);
+    assert!(
+        errors.contains("grep-lite: a-truncated.txt.gz: "),
+        "{}",
+        errors
+    );
+    assert!(errors.contains("grep-lite: b-corrupt.gz: "), "{}", errors);
+}
+
+#[cfg(feature = "bzip2")]
+#[test]
+fn bzip2_files_are_searched_decompressed() {
+    let output = grep_lite(&fixtures(), &["beta", "lines.txt.bz2"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "beta\n");
+}
+
+#[cfg(feature = "bzip2")]
+#[test]
+fn decompress_finds_bzip2_files_by_their_first_bytes() {
+    let output = grep_lite(&fixtures(), &["-z", "beta", "lines-bzip2.dat", "lines.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "lines-bzip2.dat:beta\nlines.txt:beta\n");
+}
+
+#[cfg(not(feature = "bzip2"))]
+#[test]
+fn bzip2_files_are_skipped_without_the_feature() {
+    let output = grep_lite(&fixtures(), &["beta", "lines.txt.bz2"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("built without the `bzip2` feature"));
+}
+
+#[cfg(feature = "xz")]
+#[test]
+fn xz_and_lzma_files_are_searched_decompressed() {
+    let output = grep_lite(&fixtures(), &["beta", "lines.txt.xz", "lines.txt.lzma"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "lines.txt.xz:beta\nlines.txt.lzma:beta\n");
+}
+
+#[cfg(feature = "xz")]
+#[test]
+fn decompress_finds_xz_files_by_their_first_bytes() {
+    let output = grep_lite(&fixtures(), &["-z", "beta", "lines-xz.dat", "lines.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "lines-xz.dat:beta\nlines.txt:beta\n");
+}
+
+#[cfg(not(feature = "xz"))]
+#[test]
+fn xz_files_are_skipped_without_the_feature() {
+    let output = grep_lite(&fixtures(), &["beta", "lines.txt.xz"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("built without the `xz` feature"));
+}
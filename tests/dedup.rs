@@ -0,0 +1,44 @@
+mod common;
+
+use common::{grep_lite, stdout, temp_dir};
+use std::{fs, path::PathBuf};
+
+// `b.txt` starts with the line `a.txt` ends with
+fn repeated_lines(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::write(dir.join("a.txt"), "err x\nerr x\nerr y\nerr x\n").unwrap();
+    fs::write(dir.join("b.txt"), "err x\nerr x\n").unwrap();
+    dir
+}
+
+#[test]
+fn dedup_squeezes_runs_of_the_same_line() {
+    let dir = repeated_lines("dedup_squeezes_runs");
+
+    let output = grep_lite(&dir, &["--dedup", "err", "a.txt"]);
+
+    assert_eq!(stdout(&output), "err x\nerr y\nerr x\n");
+}
+
+#[test]
+fn dedup_starts_afresh_in_each_file() {
+    let dir = repeated_lines("dedup_starts_afresh_in_each_file");
+
+    for threads in ["1", "4"] {
+        let output = grep_lite(&dir, &["-r", "--threads", threads, "--dedup", "err"]);
+
+        assert_eq!(
+            stdout(&output),
+            "a.txt:err x\na.txt:err y\na.txt:err x\nb.txt:err x\n"
+        );
+    }
+}
+
+#[test]
+fn dedup_leaves_counts_alone() {
+    let dir = repeated_lines("dedup_leaves_counts_alone");
+
+    let output = grep_lite(&dir, &["-r", "--dedup", "-c", "err"]);
+
+    assert_eq!(stdout(&output), "a.txt: 4\nb.txt: 2\n");
+}
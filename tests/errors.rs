@@ -0,0 +1,77 @@
+mod common;
+
+use common::{grep_lite, stderr, stdout, temp_dir};
+use std::fs;
+
+fn readable_files(name: &str) -> std::path::PathBuf {
+    let dir = temp_dir(name);
+    fs::write(dir.join("r1.txt"), "b\n").unwrap();
+    fs::write(dir.join("r2.txt"), "b\n").unwrap();
+    dir
+}
+
+#[test]
+fn missing_files_are_reported_and_the_rest_searched() {
+    let dir = readable_files("missing_files_are_reported");
+
+    for threads in ["1", "4"] {
+        let output = grep_lite(
+            &dir,
+            &["--threads", threads, "b", "r1.txt", "missing.txt", "r2.txt"],
+        );
+
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(stdout(&output), "r1.txt:b\nr2.txt:b\n");
+        assert_eq!(
+            stderr(&output),
+            "grep-lite: missing.txt: No such file or directory\n"
+        );
+    }
+}
+
+#[test]
+fn no_messages_still_exits_with_2() {
+    let dir = readable_files("no_messages_still_exits_with_2");
+
+    let output = grep_lite(&dir, &["-s", "b", "r1.txt", "missing.txt", "r2.txt"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "r1.txt:b\nr2.txt:b\n");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn quiet_with_a_match_exits_with_0_despite_missing_files() {
+    let dir = readable_files("quiet_with_a_match");
+
+    let output = grep_lite(&dir, &["-q", "b", "missing.txt", "r1.txt"]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = grep_lite(&dir, &["-q", "x", "missing.txt", "r1.txt"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn huge_patterns_need_a_raised_size_limit() {
+    let dir = temp_dir("huge_patterns_need_a_raised_size_limit");
+    let word = "x".repeat(300);
+    fs::write(dir.join("words.txt"), format!("short\n{word}\n")).unwrap();
+    // Each Unicode `\w` compiles to tens of kilobytes, so this goes well
+    // past the regex crate's default limit of 10M
+    let pattern = r"\w".repeat(300);
+
+    let output = grep_lite(&dir, &[&pattern, "words.txt"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(
+        stderr(&output),
+        "grep-lite: the pattern is too big, compiling to more than the limit of \
+         10485760 bytes; raise it with --regex-size-limit\n"
+    );
+
+    let output = grep_lite(&dir, &["--regex-size-limit", "100M", &pattern, "words.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), format!("{word}\n"));
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use common::{fixtures, grep_lite, grep_lite_with_stdin, stderr, stdout};
+use grep_lite::json::{Message, Text};
+use std::fs;
+
+// `invalid-utf8.txt` holds `foo ok`, `foo \xff bad` and `foo after`
+const INVALID_UTF8_MATCHES: &str = concat!(
+    r#"{"type":"begin","data":{"path":{"text":"invalid-utf8.txt"}}}"#,
+    "\n",
+    r#"{"type":"match","data":{"path":{"text":"invalid-utf8.txt"},"lines":{"text":"foo ok"},"line_number":1,"absolute_offset":0,"submatches":[{"match":{"text":"foo"},"start":0,"end":3}]}}"#,
+    "\n",
+    r#"{"type":"match","data":{"path":{"text":"invalid-utf8.txt"},"lines":{"bytes":"Zm9vIP8gYmFk"},"line_number":2,"absolute_offset":7,"submatches":[{"match":{"text":"foo"},"start":0,"end":3}]}}"#,
+    "\n",
+    r#"{"type":"match","data":{"path":{"text":"invalid-utf8.txt"},"lines":{"text":"foo after"},"line_number":3,"absolute_offset":17,"submatches":[{"match":{"text":"foo"},"start":0,"end":3}]}}"#,
+    "\n",
+    r#"{"type":"end","data":{"path":{"text":"invalid-utf8.txt"},"stats":{"count":3}}}"#,
+    "\n",
+    r#"{"type":"summary","data":{"stats":{"count":3}}}"#,
+    "\n",
+);
+
+#[test]
+fn lines_that_are_not_utf8_are_given_as_bytes() {
+    for mode in ["--mmap", "--no-mmap"] {
+        let output = grep_lite(&fixtures(), &[mode, "--json", "foo", "invalid-utf8.txt"]);
+
+        assert_eq!(output.status.code(), Some(0), "{}", mode);
+        assert_eq!(stdout(&output), INVALID_UTF8_MATCHES, "{}", mode);
+        assert_eq!(stderr(&output), "", "{}", mode);
+    }
+
+    let line: Message = serde_json::from_str(INVALID_UTF8_MATCHES.lines().nth(2).unwrap()).unwrap();
+    let Message::Match(lines) = line else {
+        panic!("expected a match message");
+    };
+    assert_eq!(lines.lines, Text::Bytes(b"foo \xff bad".to_vec()));
+}
+
+#[test]
+fn lines_from_stdin_that_are_not_utf8_are_given_as_bytes() {
+    let contents = fs::read(fixtures().join("invalid-utf8.txt")).unwrap();
+
+    let output = grep_lite_with_stdin(
+        &fixtures(),
+        &["--json", "--label", "invalid-utf8.txt", "-H", "foo"],
+        &contents,
+    );
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), INVALID_UTF8_MATCHES);
+}
+
+#[test]
+fn context_and_matches_in_lines_that_are_not_utf8_are_given_as_bytes() {
+    let output = grep_lite(&fixtures(), &["--json", "-A1", "ok", "invalid-utf8.txt"]);
+    let context = stdout(&output).lines().nth(2).unwrap().to_string();
+    assert_eq!(
+        context,
+        r#"{"type":"context","data":{"path":{"text":"invalid-utf8.txt"},"lines":{"bytes":"Zm9vIP8gYmFk"},"line_number":2,"absolute_offset":7,"submatches":[]}}"#
+    );
+
+    // Offsets are into the line's bytes, after the one invalid byte
+    let output = grep_lite(&fixtures(), &["--json", "bad", "invalid-utf8.txt"]);
+    let matched = stdout(&output).lines().nth(1).unwrap().to_string();
+    assert!(
+        matched.ends_with(r#""submatches":[{"match":{"text":"bad"},"start":6,"end":9}]}}"#),
+        "{}",
+        matched
+    );
+}
+
+#[test]
+fn text_output_searches_past_lines_that_are_not_utf8() {
+    let output = grep_lite(&fixtures(), &["-n", "foo", "invalid-utf8.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        "1: foo ok\n2: foo \u{FFFD} bad\n3: foo after\n"
+    );
+}
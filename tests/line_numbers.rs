@@ -0,0 +1,64 @@
+mod common;
+
+use common::{grep_lite, stdout, temp_dir};
+use std::{fs, path::PathBuf};
+
+fn two_files(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::write(dir.join("a.txt"), "a\nb\nab\n").unwrap();
+    fs::write(dir.join("b.txt"), "b\n").unwrap();
+    dir
+}
+
+// Lines are printed as grep prints them unless `-n` is given
+#[test]
+fn line_numbers_are_off_by_default() {
+    let dir = two_files("line_numbers_are_off_by_default");
+
+    let output = grep_lite(&dir, &["b", "a.txt"]);
+    assert_eq!(stdout(&output), "b\nab\n");
+
+    let output = grep_lite(&dir, &["b", "a.txt", "b.txt"]);
+    assert_eq!(stdout(&output), "a.txt:b\na.txt:ab\nb.txt:b\n");
+}
+
+// `-n` gives the output every search used to have
+#[test]
+fn line_number_prints_them_as_before() {
+    let dir = two_files("line_number_prints_them_as_before");
+
+    let output = grep_lite(&dir, &["-n", "b", "a.txt"]);
+    assert_eq!(stdout(&output), "2: b\n3: ab\n");
+
+    let output = grep_lite(&dir, &["--line-number", "b", "a.txt", "b.txt"]);
+    assert_eq!(stdout(&output), "a.txt:2: b\na.txt:3: ab\nb.txt:1: b\n");
+}
+
+#[test]
+fn line_numbers_come_before_columns_and_offsets() {
+    let dir = two_files("line_numbers_before_columns");
+
+    let output = grep_lite(&dir, &["-n", "--column", "-b", "b", "a.txt"]);
+
+    assert_eq!(stdout(&output), "2:1:2: b\n3:2:5: ab\n");
+}
+
+#[test]
+fn initial_tab_puts_a_tab_byte_before_each_line() {
+    let dir = two_files("initial_tab_puts_a_tab_byte");
+
+    let output = grep_lite(&dir, &["-T", "b", "a.txt", "b.txt"]);
+    assert_eq!(output.stdout, b"a.txt:\tb\na.txt:\tab\nb.txt:\tb\n");
+
+    let output = grep_lite(&dir, &["--initial-tab", "-n", "b", "a.txt"]);
+    assert_eq!(output.stdout, b"2:\tb\n3:\tab\n");
+}
+
+#[test]
+fn initial_tab_with_headings_goes_after_the_line_number() {
+    let dir = two_files("initial_tab_with_headings");
+
+    let output = grep_lite(&dir, &["-T", "-n", "--heading", "b", "a.txt", "b.txt"]);
+
+    assert_eq!(output.stdout, b"a.txt\n2:\tb\n3:\tab\n\nb.txt\n1:\tb\n");
+}
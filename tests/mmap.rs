@@ -0,0 +1,32 @@
+mod common;
+
+use common::{grep_lite, grep_lite_with_stdin, temp_dir};
+use std::fs;
+
+#[test]
+fn mmap_no_mmap_and_stdin_print_the_same() {
+    let dir = temp_dir("mmap_no_mmap_and_stdin");
+    let contents = "needle first\nhay\n\nhay needle\r\nhay\nhay\nlast needle";
+    fs::write(dir.join("a.txt"), contents).unwrap();
+
+    for args in [
+        &["-n"][..],
+        &["-n", "-C", "1"],
+        &["-v"],
+        &["-c"],
+        &["-o", "-b"],
+    ] {
+        let searched = |mode: &str| {
+            let mut all = args.to_vec();
+            all.extend([mode, "needle", "a.txt"]);
+            grep_lite(&dir, &all).stdout
+        };
+
+        let mut stdin_args = args.to_vec();
+        stdin_args.push("needle");
+        let stdin = grep_lite_with_stdin(&dir, &stdin_args, contents.as_bytes()).stdout;
+
+        assert_eq!(searched("--mmap"), stdin, "{:?}", args);
+        assert_eq!(searched("--no-mmap"), stdin, "{:?}", args);
+    }
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::{grep_lite, stdout, temp_dir};
+use std::{fs, path::PathBuf};
+
+// Two lazy matches of `BEGIN.*?END`, across lines 2-4 and lines 6-7. The
+// first stops at the first END on line 4.
+fn blocks(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::write(
+        dir.join("a.txt"),
+        "zero\npre BEGIN one\ntwo\nthree END post END\nfour\nBEGIN again\nEND\n",
+    )
+    .unwrap();
+    dir
+}
+
+const ARGS: [&str; 5] = ["-n", "-U", "--multiline-dotall", "BEGIN.*?END", "a.txt"];
+
+#[test]
+fn lazy_match_prints_every_line_it_spans() {
+    let dir = blocks("lazy_match_prints_every_line_it_spans");
+
+    let output = grep_lite(&dir, &ARGS);
+
+    assert_eq!(
+        stdout(&output),
+        "2: pre BEGIN one\ntwo\nthree END post END\n6: BEGIN again\nEND\n"
+    );
+}
+
+#[test]
+fn lazy_match_is_highlighted_only_where_it_matched() {
+    let dir = blocks("lazy_match_is_highlighted_only_where_it_matched");
+
+    let mut args = vec!["--color=always"];
+    args.extend(ARGS);
+    let output = grep_lite(&dir, &args);
+
+    // Each line of the match is coloured on its own, and `post END` after
+    // the lazy match ends isn't coloured at all
+    assert_eq!(
+        stdout(&output),
+        "\x1b[32m2\x1b[0m\x1b[36m:\x1b[0m pre \x1b[1;91mBEGIN one\x1b[0m\n\
+         \x1b[1;91mtwo\x1b[0m\n\
+         \x1b[1;91mthree END\x1b[0m post END\n\
+         \x1b[32m6\x1b[0m\x1b[36m:\x1b[0m \x1b[1;91mBEGIN again\x1b[0m\n\
+         \x1b[1;91mEND\x1b[0m\n"
+    );
+}
+
+#[test]
+fn lazy_match_alone_is_printed_with_only_matching() {
+    let dir = blocks("lazy_match_alone_with_only_matching");
+
+    let mut args = vec!["-o"];
+    args.extend(ARGS);
+    let output = grep_lite(&dir, &args);
+
+    assert_eq!(
+        stdout(&output),
+        "2: BEGIN one\ntwo\nthree END\n6: BEGIN again\nEND\n"
+    );
+}
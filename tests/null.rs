@@ -0,0 +1,47 @@
+mod common;
+
+use common::{grep_lite, temp_dir};
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+#[test]
+fn null_ends_file_names_with_a_nul_byte() {
+    let dir = temp_dir("null_ends_file_names");
+    fs::write(dir.join("it's a file.txt"), "needle\n").unwrap();
+    fs::write(dir.join("other.txt"), "hay\n").unwrap();
+
+    let output = grep_lite(&dir, &["-r", "-l", "-Z", "needle"]);
+    assert_eq!(output.stdout, b"it's a file.txt\0");
+
+    let output = grep_lite(&dir, &["-r", "--null", "needle"]);
+    assert_eq!(output.stdout, b"it's a file.txt\0needle\n");
+}
+
+// The names `-l -Z` prints go through `xargs -0` untouched, however they're
+// spelled
+#[cfg(unix)]
+#[test]
+fn null_file_names_round_trip_through_xargs() {
+    let dir = temp_dir("null_file_names_round_trip");
+    fs::write(dir.join("it's a file.txt"), "needle\n").unwrap();
+    fs::write(dir.join("say \"hi\".txt"), "needle\n").unwrap();
+    fs::write(dir.join("other.txt"), "hay\n").unwrap();
+
+    let names = grep_lite(&dir, &["-r", "-l", "-Z", "needle"]).stdout;
+    let mut xargs = Command::new("xargs")
+        .args(["-0", "ls", "--"])
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    xargs.stdin.take().unwrap().write_all(&names).unwrap();
+    let output = xargs.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"it's a file.txt\nsay \"hi\".txt\n");
+}
@@ -0,0 +1,24 @@
+mod common;
+
+use common::{grep_lite, stdout, temp_dir};
+use std::fs;
+
+#[test]
+fn only_matching_inverted_prints_what_is_between_matches() {
+    let dir = temp_dir("only_matching_inverted_prints_what_is_between_matches");
+    fs::write(dir.join("a.txt"), "a1b22c\nno digits\n123\n").unwrap();
+
+    let output = grep_lite(&dir, &["-o", "-v", "-n", r"\d+", "a.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    // A fully matched line prints nothing, and one without a match is whole
+    assert_eq!(stdout(&output), "1: a\n1: b\n1: c\n2: no digits\n");
+
+    // Only lines without a match count
+    let output = grep_lite(&dir, &["-o", "-v", "--total", r"\d+", "a.txt"]);
+    assert!(
+        stdout(&output).ends_with("total: 1\n"),
+        "{}",
+        stdout(&output)
+    );
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use common::{grep_lite, stderr, stdout, temp_dir};
+use std::fs;
+
+#[test]
+fn output_that_is_an_input_is_refused_untouched() {
+    let dir = temp_dir("output_that_is_an_input");
+    fs::write(dir.join("data.txt"), "abc\nxyz\nb\n").unwrap();
+
+    let output = grep_lite(&dir, &["b", "data.txt", "--output", "data.txt"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("data.txt: input file is also the output"));
+    assert_eq!(
+        fs::read_to_string(dir.join("data.txt")).unwrap(),
+        "abc\nxyz\nb\n"
+    );
+}
+
+#[test]
+fn output_inside_a_recursive_search_is_refused_untouched() {
+    let dir = temp_dir("output_inside_a_recursive_search");
+    fs::write(dir.join("a.txt"), "b\n").unwrap();
+    fs::write(dir.join("results.txt"), "old b\n").unwrap();
+
+    let output = grep_lite(&dir, &["-r", "b", ".", "--output", "results.txt"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        fs::read_to_string(dir.join("results.txt")).unwrap(),
+        "old b\n"
+    );
+}
+
+#[test]
+fn output_goes_to_the_file() {
+    let dir = temp_dir("output_goes_to_the_file");
+    fs::write(dir.join("data.txt"), "abc\nxyz\nb\n").unwrap();
+
+    let output = grep_lite(&dir, &["b", "data.txt", "--output", "out.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(fs::read_to_string(dir.join("out.txt")).unwrap(), "abc\nb\n");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn write_errors_are_reported() {
+    let dir = temp_dir("write_errors_are_reported");
+    fs::write(dir.join("data.txt"), "abc\nxyz\nb\n").unwrap();
+
+    let output = grep_lite(&dir, &["b", "data.txt", "--output", "/dev/full"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    let message = stderr(&output);
+    assert!(message.starts_with("grep-lite: "), "{}", message);
+    assert!(!message.contains("Os {"), "{}", message);
+}
+
+#[test]
+fn closed_pipe_ends_the_search_quietly() {
+    use std::io::Read;
+    use std::process::{Command, Stdio};
+
+    let dir = temp_dir("closed_pipe_ends_the_search_quietly");
+    let lines = "b\n".repeat(1000);
+    for i in 0..200 {
+        fs::write(dir.join(format!("p{}.txt", i)), &lines).unwrap();
+    }
+    let files: Vec<String> = (0..200).map(|i| format!("p{}.txt", i)).collect();
+
+    for threads in ["1", "4"] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_grep-lite-2"))
+            .arg("b")
+            .args(&files)
+            .args(["--threads", threads])
+            .current_dir(&dir)
+            .env_remove("GREP_LITE_OPTIONS")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // Read a little, as `head` would, then close the pipe
+        let mut start = [0; 64];
+        child.stdout.take().unwrap().read_exact(&mut start).unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(stderr(&output), "");
+    }
+}
@@ -0,0 +1,244 @@
+mod common;
+
+use common::{grep_lite, grep_lite_with_stdin, stderr, stdout, temp_dir};
+use std::{fs, path::PathBuf};
+
+// A tree with an `f.txt` holding "needle" at each depth: `f.txt`, `a/f.txt`,
+// `a/b/f.txt` and `a/b/c/f.txt`
+fn nested_tree(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::create_dir_all(dir.join("a/b/c")).unwrap();
+    for sub in ["", "a", "a/b", "a/b/c"] {
+        fs::write(dir.join(sub).join("f.txt"), "needle\n").unwrap();
+    }
+    dir
+}
+
+#[test]
+fn max_depth_limits_how_far_down_a_search_goes() {
+    let dir = nested_tree("max_depth_limits");
+    let found = |depth: &str| {
+        stdout(&grep_lite(
+            &dir,
+            &["-r", "-l", "--max-depth", depth, "needle"],
+        ))
+    };
+
+    assert_eq!(found("0"), "");
+    assert_eq!(found("1"), "f.txt\n");
+    assert_eq!(found("2"), "a/f.txt\nf.txt\n");
+    assert_eq!(found("3"), "a/b/f.txt\na/f.txt\nf.txt\n");
+    assert_eq!(found("4"), "a/b/c/f.txt\na/b/f.txt\na/f.txt\nf.txt\n");
+    assert_eq!(found("10"), found("4"));
+}
+
+#[test]
+fn max_depth_works_with_directories_recurse() {
+    let dir = nested_tree("max_depth_works_with_directories_recurse");
+
+    let output = grep_lite(&dir, &["-d", "recurse", "-l", "--max-depth", "1", "needle"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "f.txt\n");
+
+    let output = grep_lite(
+        &dir,
+        &["-d", "recurse", "-l", "--max-depth", "2", "needle", "a"],
+    );
+    assert_eq!(stdout(&output), "a/b/f.txt\na/f.txt\n");
+}
+
+#[test]
+fn max_depth_needs_recursion() {
+    let dir = nested_tree("max_depth_needs_recursion");
+
+    for args in [
+        &["--max-depth", "1"][..],
+        &["-d", "read", "--max-depth", "1"],
+    ] {
+        let mut args = args.to_vec();
+        args.extend(["needle", "f.txt"]);
+        let output = grep_lite(&dir, &args);
+
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        assert_eq!(stdout(&output), "");
+        assert_eq!(
+            stderr(&output),
+            "grep-lite: --max-depth needs -r or -d recurse\n"
+        );
+    }
+}
+
+#[test]
+fn max_depth_counts_from_each_input() {
+    let dir = nested_tree("max_depth_counts_from_each_input");
+
+    let output = grep_lite(
+        &dir,
+        &["-r", "-l", "--max-depth", "1", "needle", "a", "a/b"],
+    );
+    assert_eq!(stdout(&output), "a/f.txt\na/b/f.txt\n");
+
+    // An input that is a file is still searched at depth 0
+    let output = grep_lite(&dir, &["-r", "-l", "--max-depth", "0", "needle", "f.txt"]);
+    assert_eq!(stdout(&output), "f.txt\n");
+}
+
+#[test]
+fn recursive_search_with_no_inputs_searches_the_current_directory() {
+    let dir = nested_tree("recursive_search_with_no_inputs");
+
+    let output = grep_lite_with_stdin(&dir, &["-r", "-l", "needle"], b"needle from stdin\n");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "a/b/c/f.txt\na/b/f.txt\na/f.txt\nf.txt\n");
+}
+
+#[test]
+fn recursive_output_is_the_same_every_time() {
+    let dir = temp_dir("recursive_output_is_the_same_every_time");
+    // Created out of order, so the order directories list them in isn't
+    // the sorted one
+    for name in [
+        "m/z.txt", "c.txt", "m/a.txt", "zz/q.txt", "a.txt", "b/x.txt", "m/k.txt",
+    ] {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "needle\n").unwrap();
+    }
+
+    let first = grep_lite(&dir, &["-r", "needle"]);
+    let second = grep_lite(&dir, &["-r", "needle"]);
+
+    assert_eq!(first.stdout, second.stdout);
+    assert_eq!(
+        stdout(&first),
+        "a.txt:needle\nb/x.txt:needle\nc.txt:needle\nm/a.txt:needle\nm/k.txt:needle\n\
+         m/z.txt:needle\nzz/q.txt:needle\n"
+    );
+}
+
+#[test]
+fn bare_recursive_search_names_files_without_a_leading_dot() {
+    let dir = nested_tree("bare_recursive_search");
+
+    let output = grep_lite(&dir, &["-r", "needle"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        "a/b/c/f.txt:needle\na/b/f.txt:needle\na/f.txt:needle\nf.txt:needle\n"
+    );
+}
+
+#[test]
+fn standard_input_is_only_read_without_recursion() {
+    let dir = nested_tree("standard_input_only_without_recursion");
+
+    let output = grep_lite_with_stdin(&dir, &["needle"], b"needle stdin\n");
+    assert_eq!(stdout(&output), "needle stdin\n");
+
+    let output = grep_lite_with_stdin(&dir, &["-r", "needle", "-"], b"needle stdin\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn overlapping_directories_search_each_file_once() {
+    let dir = nested_tree("overlapping_directories");
+
+    let output = grep_lite(&dir, &["-r", "needle", "a", "a/b"]);
+    assert_eq!(
+        stdout(&output),
+        "a/b/c/f.txt:needle\na/b/f.txt:needle\na/f.txt:needle\n"
+    );
+
+    // The first time a file is found is the one kept
+    let output = grep_lite(&dir, &["-r", "-c", "needle", "a/b", "a", "./a/b/c"]);
+    assert_eq!(
+        stdout(&output),
+        "a/b/c/f.txt: 1\na/b/f.txt: 1\na/f.txt: 1\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn followed_links_into_searched_directories_search_each_file_once() {
+    let dir = nested_tree("followed_links_into_searched_directories");
+    std::os::unix::fs::symlink("a/b", dir.join("link")).unwrap();
+
+    let output = grep_lite(&dir, &["-r", "-L", "-l", "needle"]);
+
+    assert_eq!(stdout(&output), "a/b/c/f.txt\na/b/f.txt\na/f.txt\nf.txt\n");
+}
+
+#[test]
+fn ignore_files_skip_the_paths_they_match() {
+    let dir = temp_dir("ignore_files_skip_the_paths_they_match");
+    for path in [
+        "top.txt",
+        "keep.log",
+        "debug.log",
+        "src/top.txt",
+        "src/trace.log",
+        "build/out.txt",
+        "src/build/out.txt",
+        "src/build.txt",
+    ] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "needle\n").unwrap();
+    }
+    fs::write(
+        dir.join("ignore"),
+        "# logs, but not keep.log\n*.log\n!keep.log\n\n/top.txt\nbuild/\n",
+    )
+    .unwrap();
+    fs::write(dir.join("more-ignore"), "src/*.txt\n").unwrap();
+
+    let output = grep_lite(&dir, &["-r", "-l", "--ignore-file", "ignore", "needle"]);
+    assert_eq!(stdout(&output), "keep.log\nsrc/build.txt\nsrc/top.txt\n");
+
+    let output = grep_lite(
+        &dir,
+        &[
+            "-r",
+            "-l",
+            "--ignore-file",
+            "ignore",
+            "--ignore-file",
+            "more-ignore",
+            "needle",
+        ],
+    );
+    assert_eq!(stdout(&output), "keep.log\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn file_names_that_are_not_utf8_are_reported_and_skipped() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let dir = nested_tree("file_names_that_are_not_utf8");
+    fs::write(dir.join(OsStr::from_bytes(b"bad\xff.txt")), "needle\n").unwrap();
+
+    let output = grep_lite(&dir, &["-r", "-l", "needle"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "a/b/c/f.txt\na/b/f.txt\na/f.txt\nf.txt\n");
+    assert_eq!(
+        stderr(&output),
+        "grep-lite: bad\u{FFFD}.txt: skipping a file name that isn't valid UTF-8\n"
+    );
+}
+
+#[test]
+fn skipped_directories_still_count_towards_naming_files() {
+    let dir = nested_tree("skipped_directories_still_count_towards_naming_files");
+
+    let output = grep_lite(&dir, &["-d", "skip", "needle", "a", "f.txt"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "f.txt:needle\n");
+
+    let output = grep_lite(&dir, &["-d", "skip", "needle", "f.txt"]);
+    assert_eq!(stdout(&output), "needle\n");
+}
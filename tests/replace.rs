@@ -0,0 +1,60 @@
+mod common;
+
+use common::{grep_lite, stdout, temp_dir};
+use std::fs;
+
+fn price_file(name: &str) -> std::path::PathBuf {
+    let dir = temp_dir(name);
+    fs::write(dir.join("r.txt"), "price 42 usd\n").unwrap();
+    dir
+}
+
+#[test]
+fn double_dollar_is_a_literal_dollar() {
+    let dir = price_file("double_dollar_is_a_literal_dollar");
+
+    let output = grep_lite(&dir, &["--replace", "$$$1", r"(\d+)", "r.txt"]);
+    assert_eq!(stdout(&output), "price $42 usd\n");
+
+    let output = grep_lite(&dir, &["-o", "--replace", "<$$>", r"(\d+)", "r.txt"]);
+    assert_eq!(stdout(&output), "<$>\n");
+}
+
+#[test]
+fn groups_that_dont_exist_are_replaced_by_nothing() {
+    let dir = price_file("groups_that_dont_exist");
+
+    let output = grep_lite(&dir, &["--replace", "[$5]", r"(\d+)", "r.txt"]);
+    assert_eq!(stdout(&output), "price [] usd\n");
+
+    let output = grep_lite(&dir, &["--replace", "[${name}]", r"(\d+)", "r.txt"]);
+    assert_eq!(stdout(&output), "price [] usd\n");
+}
+
+#[test]
+fn group_names_run_on_unless_braced() {
+    let dir = price_file("group_names_run_on_unless_braced");
+
+    // `$1x` names the group `1x`, which doesn't exist
+    let output = grep_lite(&dir, &["--replace", "$1x", r"(\d+)", "r.txt"]);
+    assert_eq!(stdout(&output), "price  usd\n");
+
+    let output = grep_lite(&dir, &["--replace", "${1}x", r"(\d+)", "r.txt"]);
+    assert_eq!(stdout(&output), "price 42x usd\n");
+}
+
+#[test]
+fn in_place_expands_the_replacement_the_same_way() {
+    let dir = price_file("in_place_expands_the_same_way");
+
+    let output = grep_lite(
+        &dir,
+        &["--in-place", "--replace", "$$$1[$9]", r"(\d+)", "r.txt"],
+    );
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        fs::read_to_string(dir.join("r.txt")).unwrap(),
+        "price $42[] usd\n"
+    );
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use common::{grep_lite_with_stdin, stderr, stdout, temp_dir};
+use std::{fs, path::PathBuf};
+
+fn file_with_needle(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::write(dir.join("f.txt"), "needle in file\n").unwrap();
+    dir
+}
+
+#[test]
+fn dash_among_files_reads_standard_input() {
+    let dir = file_with_needle("dash_among_files_reads_standard_input");
+
+    let output = grep_lite_with_stdin(&dir, &["needle", "-", "f.txt"], b"needle in stdin\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        "(standard input):needle in stdin\nf.txt:needle in file\n"
+    );
+
+    let output = grep_lite_with_stdin(
+        &dir,
+        &["--label", "piped", "-c", "needle", "f.txt", "-"],
+        b"needle in stdin\nneedle again\n",
+    );
+    assert_eq!(stdout(&output), "f.txt: 1\npiped: 2\n");
+
+    // Only named when asked for, as the only input
+    let output = grep_lite_with_stdin(&dir, &["-H", "needle"], b"needle in stdin\n");
+    assert_eq!(stdout(&output), "(standard input):needle in stdin\n");
+}
+
+#[test]
+fn standard_input_given_twice_is_only_read_once() {
+    let dir = file_with_needle("standard_input_given_twice_is_only_read_once");
+
+    let output = grep_lite_with_stdin(&dir, &["needle", "-", "-"], b"needle in stdin\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "(standard input):needle in stdin\n");
+
+    // The second time finds it already read to the end
+    let output = grep_lite_with_stdin(&dir, &["-c", "needle", "-", "-"], b"needle in stdin\n");
+    assert_eq!(
+        stdout(&output),
+        "(standard input): 1\n(standard input): 0\n"
+    );
+}
+
+#[test]
+fn standard_input_is_not_searched_recursively() {
+    let dir = file_with_needle("standard_input_is_not_searched_recursively");
+
+    for args in [&["-r", "needle", "-"][..], &["-r", "needle", "f.txt", "-"]] {
+        let output = grep_lite_with_stdin(&dir, args, b"needle in stdin\n");
+
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        assert_eq!(stdout(&output), "");
+        assert_eq!(
+            stderr(&output),
+            "grep-lite: standard input can't be searched recursively\n"
+        );
+    }
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::{grep_lite, stdout, temp_dir};
+use std::fs;
+
+#[test]
+fn no_unicode_ignores_case_only_for_ascii_letters() {
+    let dir = temp_dir("no_unicode_ignores_case_only_for_ascii_letters");
+    fs::write(
+        dir.join("names.txt"),
+        "kelvin\nKELVIN\n\u{212A}ELVIN\n\u{c9}MILE\n",
+    )
+    .unwrap();
+
+    let output = grep_lite(&dir, &["-i", "kelvin", "names.txt"]);
+    assert_eq!(stdout(&output), "kelvin\nKELVIN\n\u{212A}ELVIN\n");
+
+    let output = grep_lite(&dir, &["-i", "--no-unicode", "kelvin", "names.txt"]);
+    assert_eq!(stdout(&output), "kelvin\nKELVIN\n");
+
+    let output = grep_lite(&dir, &["-i", "--no-unicode", "\u{e9}mile", "names.txt"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "");
+
+    // The last of `--unicode` and `--no-unicode` counts
+    let output = grep_lite(
+        &dir,
+        &["-i", "--no-unicode", "--unicode", "\u{e9}mile", "names.txt"],
+    );
+    assert_eq!(stdout(&output), "\u{c9}MILE\n");
+}
+
+#[test]
+fn no_unicode_smart_case_still_ignores_ascii_case() {
+    let dir = temp_dir("no_unicode_smart_case_still_ignores_ascii_case");
+    fs::write(dir.join("names.txt"), "kelvin\nKELVIN\n\u{212A}ELVIN\n").unwrap();
+
+    let output = grep_lite(&dir, &["-S", "--no-unicode", "kelvin", "names.txt"]);
+
+    assert_eq!(stdout(&output), "kelvin\nKELVIN\n");
+}